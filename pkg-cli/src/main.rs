@@ -1,7 +1,21 @@
-use std::{cell::RefCell, io, process, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    time::Duration,
+};
 
-use clap::{Parser, Subcommand};
-use pkg::{callback::IndicatifCallback, Library, PackageName};
+use clap::{Parser, Subcommand, ValueEnum};
+use pkg::{
+    callback::{IndicatifCallback, PlainCallback},
+    DependencyTree, HistoryEntry, HistoryPackage, InstallState, Library, LockMode, PackageAction,
+    PackageFileEntry, PackageName, RepoConfig, RepoManager, RepoPublicKeyFile, TransactionPlan,
+    VerifyIssue, VerifyIssueKind,
+};
+use serde_derive::Serialize;
 use termion::{color, is_tty, style};
 
 /// Redox Package Manager
@@ -9,6 +23,30 @@ use termion::{color, is_tty, style};
 #[command(name = "pkg")]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// print the transaction plan without changing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// root directory of the installation to manage
+    #[arg(long, global = true, env = "PKG_ROOT")]
+    root: Option<PathBuf>,
+
+    /// target triple of the installation to manage
+    #[arg(long, global = true, env = "PKG_TARGET")]
+    target: Option<String>,
+
+    /// directory of downloaded archives, default is var/cache/pkg inside root
+    #[arg(long, global = true, env = "PKG_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// seconds to wait for the package database lock held by another process
+    #[arg(long, global = true, env = "PKG_LOCK_TIMEOUT")]
+    lock_timeout: Option<u64>,
+
+    /// output format of list, search, info and transaction plans
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -18,11 +56,15 @@ enum Commands {
     /// install package(s)
     #[command(arg_required_else_help = true)]
     Install {
-        /// package(s)
+        /// package(s), or path(s) to .pkgar archives
         packages: Vec<String>,
 
         #[arg(short = 'a')]
         all: bool,
+
+        /// public key TOML to check local archives, trusted keys are used if not specified
+        #[arg(long)]
+        key: Option<PathBuf>,
     },
 
     /// remove package(s)
@@ -33,8 +75,15 @@ enum Commands {
 
         #[arg(short = 'a')]
         all: bool,
+
+        /// allow removing protected package(s), asks for confirmation
+        #[arg(long)]
+        force: bool,
     },
 
+    /// remove automatically installed packages that are no longer needed
+    Autoremove,
+
     /// update package(s) if nothing is spesified updates all installed packages
     Update {
         /// package(s)
@@ -44,6 +93,56 @@ enum Commands {
         all: bool,
     },
 
+    /// hold package(s) at their installed build
+    #[command(arg_required_else_help = true)]
+    Hold {
+        /// package(s)
+        packages: Vec<String>,
+    },
+
+    /// allow held package(s) to be updated
+    #[command(arg_required_else_help = true)]
+    Unhold {
+        /// package(s)
+        packages: Vec<String>,
+    },
+
+    /// protect package(s) from being removed
+    #[command(arg_required_else_help = true)]
+    Protect {
+        /// package(s)
+        packages: Vec<String>,
+    },
+
+    /// allow protected package(s) to be removed
+    #[command(arg_required_else_help = true)]
+    Unprotect {
+        /// package(s)
+        packages: Vec<String>,
+    },
+
+    /// download package(s) without installing
+    #[command(arg_required_else_help = true)]
+    Download {
+        /// package(s)
+        packages: Vec<String>,
+
+        /// also download dependencies
+        #[arg(long)]
+        resolve_deps: bool,
+
+        /// destination directory, current directory if not specified
+        #[arg(long)]
+        dest: Option<PathBuf>,
+    },
+
+    /// remove downloaded archives that are not used by installed packages
+    Clean {
+        /// remove all downloaded files
+        #[arg(long)]
+        all: bool,
+    },
+
     /// search for a package
     #[command(arg_required_else_help = true)]
     Search {
@@ -58,8 +157,266 @@ enum Commands {
         package: String,
     },
 
+    /// list files owned by an installed package
+    #[command(arg_required_else_help = true)]
+    Files {
+        /// package
+        package: String,
+    },
+
+    /// find installed package(s) owning a file
+    #[command(arg_required_else_help = true)]
+    Owner {
+        /// path to the file
+        path: PathBuf,
+    },
+
+    /// list dependencies of a package
+    #[command(arg_required_else_help = true)]
+    Depends {
+        /// package
+        package: String,
+
+        /// print as a tree
+        #[arg(long)]
+        tree: bool,
+
+        /// use repository metadata even if the package is installed
+        #[arg(long)]
+        remote: bool,
+
+        /// maximum depth of dependencies
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+
+    /// list installed packages depending on a package
+    #[command(arg_required_else_help = true)]
+    Rdepends {
+        /// package
+        package: String,
+
+        /// print as a tree
+        #[arg(long)]
+        tree: bool,
+
+        /// maximum depth of dependents
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+
+    /// print dependency graph of installed packages, or of package(s) from repository
+    Graph {
+        /// use installed packages (default)
+        #[arg(long, conflicts_with = "remote")]
+        installed: bool,
+
+        /// resolve package(s) and their dependencies from repository
+        #[arg(long, num_args = 1.., value_name = "PACKAGES")]
+        remote: Option<Vec<String>>,
+    },
+
+    /// explain why a package is installed
+    #[command(arg_required_else_help = true)]
+    Why {
+        /// package
+        package: String,
+    },
+
+    /// verify installed files of package(s), all installed packages if nothing is specified
+    Verify {
+        /// package(s)
+        packages: Vec<String>,
+
+        /// re-extract damaged packages from a newly downloaded archive
+        #[arg(long)]
+        repair: bool,
+    },
+
     /// list installed packages
+    List {
+        /// only list manually installed packages
+        #[arg(long, conflicts_with = "auto")]
+        manual: bool,
+
+        /// only list automatically installed packages
+        #[arg(long)]
+        auto: bool,
+    },
+
+    /// mark package(s) as manually or automatically installed
+    #[command(arg_required_else_help = true)]
+    Mark {
+        /// install reason
+        reason: MarkReason,
+
+        /// package(s)
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+
+    /// show transaction history
+    History {
+        /// show details of a transaction
+        #[arg(long)]
+        id: Option<u64>,
+    },
+
+    /// revert a transaction, the latest successful one if not specified
+    Rollback {
+        /// transaction id from history
+        id: Option<u64>,
+    },
+
+    /// finish a transaction interrupted by a crash or power loss
+    Resume {
+        /// revert the interrupted transaction instead
+        #[arg(long)]
+        rollback: bool,
+    },
+
+    /// manage package repositories in etc/pkg.d
+    #[command(subcommand)]
+    Repo(RepoCommands),
+}
+
+#[derive(Debug, Subcommand)]
+enum RepoCommands {
+    /// add a repository
+    #[command(arg_required_else_help = true)]
+    Add {
+        /// repository URL
+        url: String,
+    },
+
+    /// remove a repository
+    #[command(arg_required_else_help = true)]
+    Remove {
+        /// repository name or URL
+        repo: String,
+    },
+
+    /// list configured repositories
     List,
+
+    /// enable a disabled repository
+    #[command(arg_required_else_help = true)]
+    Enable {
+        /// repository name or URL
+        repo: String,
+    },
+
+    /// disable a repository without removing it
+    #[command(arg_required_else_help = true)]
+    Disable {
+        /// repository name or URL
+        repo: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// human readable text
+    Text,
+    Json,
+    Toml,
+    /// Graphviz DOT, only for graph
+    Dot,
+}
+
+#[derive(Serialize)]
+struct InstalledPackage<'a> {
+    name: &'a PackageName,
+    #[serde(flatten)]
+    state: &'a InstallState,
+}
+
+#[derive(Serialize)]
+struct InstalledList<'a> {
+    packages: Vec<InstalledPackage<'a>>,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    name: PackageName,
+    rank: f64,
+}
+
+#[derive(Serialize)]
+struct SearchList {
+    packages: Vec<SearchResult>,
+}
+
+#[derive(Serialize)]
+struct FileList<'a> {
+    package: &'a PackageName,
+    files: Vec<PackageFileEntry>,
+}
+
+#[derive(Serialize)]
+struct FileOwner<'a> {
+    path: &'a Path,
+    owners: Vec<PackageName>,
+}
+
+#[derive(Serialize)]
+struct WhyList<'a> {
+    package: &'a PackageName,
+    paths: Vec<Vec<PackageName>>,
+}
+
+#[derive(Serialize)]
+struct VerifyList<'a> {
+    issues: &'a [VerifyIssue],
+}
+
+#[derive(Serialize)]
+struct RepoList {
+    repos: Vec<RepoConfig>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum MarkReason {
+    /// installed explicitly, never removed by autoremove
+    Manual,
+    /// installed as a dependency
+    Auto,
+}
+
+impl Commands {
+    /// commands that write files without a transaction plan to print, `--dry-run` is refused
+    fn refuses_dry_run(&self) -> bool {
+        matches!(
+            self,
+            Commands::Download { .. }
+                | Commands::Clean { .. }
+                | Commands::Repo(
+                    RepoCommands::Add { .. }
+                        | RepoCommands::Remove { .. }
+                        | RepoCommands::Enable { .. }
+                        | RepoCommands::Disable { .. }
+                )
+        )
+    }
+
+    /// commands that only query the installation, they share the lock with each other
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Commands::Download { .. }
+                | Commands::Search { .. }
+                | Commands::Info { .. }
+                | Commands::Files { .. }
+                | Commands::Owner { .. }
+                | Commands::Depends { .. }
+                | Commands::Rdepends { .. }
+                | Commands::Graph { .. }
+                | Commands::Why { .. }
+                | Commands::List { .. }
+                | Commands::History { .. }
+                | Commands::Verify { repair: false, .. }
+        )
+    }
 }
 
 // TODO: Refactor this
@@ -92,28 +449,88 @@ fn main() {
     let mut callback = IndicatifCallback::new();
     callback.set_interactive(true);
 
-    let (install_path, target) = if cfg!(target_os = "redox") {
+    let (default_path, default_target) = if cfg!(target_os = "redox") {
         ("/", env!("TARGET"))
     } else {
         ("/tmp/pkg_install", "x86_64-unknown-redox")
     };
+    let install_path = args.root.unwrap_or_else(|| PathBuf::from(default_path));
+    let target = args.target.unwrap_or_else(|| default_target.to_string());
     let color_support_stdout = is_tty(&io::stdout());
     let color_support_stderr = is_tty(&io::stderr());
-    let mut library = Library::new(install_path, target, Rc::new(RefCell::new(callback)))
-        .unwrap_or_else(|err| {
-            eprintln!(
-                "{}Error: Failed to initialize package library: {:?}{}",
-                color::Fg(color::Red),
-                err,
-                style::Reset
-            );
-            if matches!(err, pkg::backend::Error::MissingPermissions) {
-                eprintln!("Hint: You may need root privileges. Try running with 'sudo'.");
-            }
-            std::process::exit(1);
-        });
 
-    execute_command(args.command, &mut library, color_support_stdout).unwrap_or_else(|err| {
+    if args.dry_run && args.command.refuses_dry_run() {
+        eprintln!("error: --dry-run is not supported by this command");
+        process::exit(1);
+    }
+
+    // repositories are managed without loading them
+    let command = match args.command {
+        Commands::Repo(command) => {
+            execute_repo_command(
+                command,
+                &install_path,
+                args.cache_dir.as_deref(),
+                args.format,
+                color_support_stdout,
+            )
+            .unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            });
+            return;
+        }
+        command => command,
+    };
+
+    let lock_mode = if args.dry_run || command.is_read_only() {
+        LockMode::Shared
+    } else {
+        LockMode::Exclusive
+    };
+    let lock_wait = args.lock_timeout.map(Duration::from_secs);
+    let mut library = Library::new_with_lock(
+        &install_path,
+        &target,
+        Rc::new(RefCell::new(callback)),
+        lock_mode,
+        lock_wait,
+    )
+    .unwrap_or_else(|err| {
+        eprintln!(
+            "{}Error: Failed to initialize package library: {:?}{}",
+            color::Fg(color::Red),
+            err,
+            style::Reset
+        );
+        if matches!(err, pkg::backend::Error::MissingPermissions) {
+            eprintln!("Hint: You may need root privileges. Try running with 'sudo'.");
+        }
+        if matches!(err, pkg::backend::Error::PackageLocked(_)) {
+            eprintln!("Hint: Use '--lock-timeout <SECONDS>' to wait for it.");
+        }
+        std::process::exit(1);
+    });
+    if let Some(cache_dir) = args.cache_dir {
+        library.set_cache_dir(cache_dir);
+    }
+    library.set_history_command(std::env::args().collect::<Vec<_>>().join(" "));
+    if library.get_interrupted().is_some() && !matches!(command, Commands::Resume { .. }) {
+        eprintln!(
+            "warning: a previous transaction was interrupted, run `pkg resume` to finish it \
+            or `pkg resume --rollback` to revert it"
+        );
+    }
+
+    execute_command(
+        command,
+        &mut library,
+        &install_path,
+        args.dry_run,
+        args.format,
+        color_support_stdout,
+    )
+    .unwrap_or_else(|err| {
         if color_support_stderr {
             eprintln!(
                 "{}{}error: {}{}{:?}{}",
@@ -134,19 +551,49 @@ fn main() {
 fn execute_command(
     command: Commands,
     library: &mut Library,
+    install_path: &Path,
+    dry_run: bool,
+    format: OutputFormat,
     color_support: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut needs_apply = false;
 
     match command {
-        Commands::Install { packages, all } => {
-            let packages = process_packages(packages, library, all);
-            library.install(packages)?;
+        Commands::Install { packages, all, key } => {
+            let (archives, packages): (Vec<String>, Vec<String>) =
+                packages.into_iter().partition(|p| p.ends_with(".pkgar"));
+            if !archives.is_empty() {
+                let pubkey = match key {
+                    Some(key) => Some(RepoPublicKeyFile::open(key)?.pkey),
+                    None => None,
+                };
+                let archives = archives.into_iter().map(PathBuf::from).collect();
+                library.install_local(archives, pubkey)?;
+            }
+            if all || !packages.is_empty() {
+                let packages = process_packages(packages, library, all);
+                library.install(packages)?;
+            }
             needs_apply = true;
         }
-        Commands::Remove { packages, all } => {
+        Commands::Remove {
+            packages,
+            all,
+            force,
+        } => {
             let packages = process_packages(packages, library, all);
-            library.uninstall(packages)?;
+            if force {
+                library.uninstall_force(packages)?;
+            } else {
+                library.uninstall(packages)?;
+            }
+            needs_apply = true;
+        }
+        Commands::Autoremove => {
+            let packages = library.autoremove()?;
+            if packages.is_empty() {
+                println!("No orphaned packages to remove");
+            }
             needs_apply = true;
         }
         Commands::Update { packages, all } => {
@@ -155,26 +602,329 @@ fn execute_command(
             library.update(packages)?;
             needs_apply = true;
         }
+        Commands::Hold { packages } => {
+            let packages = process_packages(packages, library, false);
+            for name in library.hold(packages)? {
+                println!("{name} held");
+            }
+            needs_apply = true;
+        }
+        Commands::Unhold { packages } => {
+            let packages = process_packages(packages, library, false);
+            for name in library.unhold(packages)? {
+                println!("{name} unheld");
+            }
+            needs_apply = true;
+        }
+        Commands::Protect { packages } => {
+            let packages = process_packages(packages, library, false);
+            for name in library.protect(packages)? {
+                println!("{name} protected");
+            }
+            needs_apply = true;
+        }
+        Commands::Unprotect { packages } => {
+            let packages = process_packages(packages, library, false);
+            for name in library.unprotect(packages.clone())? {
+                println!("{name} unprotected");
+            }
+            for name in packages {
+                if library.is_protected(&name) {
+                    eprintln!("{name} is still protected by etc/pkg/protected.d");
+                }
+            }
+            needs_apply = true;
+        }
+        Commands::Download {
+            packages,
+            resolve_deps,
+            dest,
+        } => {
+            let packages = process_packages(packages, library, false);
+            let dest = dest.unwrap_or_else(|| PathBuf::from("."));
+            for name in library.download(packages, resolve_deps, &dest)? {
+                println!("{name} downloaded to {}", dest.display());
+            }
+        }
+        Commands::Clean { all } => {
+            let (count, size) = library.clean(all)?;
+            println!(
+                "Removed {} files, {} freed",
+                count,
+                PlainCallback::format_size(size)
+            );
+        }
         Commands::Search { package } => {
             let packages = library.search(&package)?;
-            for (i, (name, _)) in packages.iter().enumerate() {
-                write_package(i, name, color_support);
+            if format == OutputFormat::Text {
+                for (i, (name, _)) in packages.iter().enumerate() {
+                    write_package(i, name, color_support);
+                }
+            } else {
+                let packages = packages
+                    .into_iter()
+                    .map(|(name, rank)| SearchResult { name, rank })
+                    .collect();
+                write_output(format, &SearchList { packages })?;
             }
         }
         Commands::Info { package } => {
             let package = PackageName::new(package)?;
             let info = library.info(package)?;
-            println!("{:#?}", info);
+            if format == OutputFormat::Text {
+                println!("{:#?}", info);
+            } else {
+                write_output(format, &info)?;
+            }
+        }
+        Commands::Files { package } => {
+            let package = PackageName::new(package)?;
+            let files = library.get_package_files(&package)?;
+            if format == OutputFormat::Text {
+                for file in &files {
+                    println!(
+                        "{:06o} {:>10} /{}",
+                        file.mode,
+                        PlainCallback::format_size(file.size),
+                        file.path.display()
+                    );
+                }
+            } else {
+                write_output(
+                    format,
+                    &FileList {
+                        package: &package,
+                        files,
+                    },
+                )?;
+            }
+        }
+        Commands::Owner { path } => {
+            // paths inside another root may be given as host paths
+            let relative = path.strip_prefix(install_path).unwrap_or(&path);
+            let owners = library.owner_of(relative)?;
+            if format != OutputFormat::Text {
+                write_output(
+                    format,
+                    &FileOwner {
+                        path: &path,
+                        owners,
+                    },
+                )?;
+            } else if owners.is_empty() {
+                println!("{} is not owned by any package", path.display());
+            } else if owners.len() == 1 {
+                println!("{} is owned by {}", path.display(), owners[0]);
+            } else {
+                let owners: Vec<&str> = owners.iter().map(|p| p.as_str()).collect();
+                println!(
+                    "{} is owned by multiple packages (conflict): {}",
+                    path.display(),
+                    owners.join(", ")
+                );
+            }
+        }
+        Commands::Depends {
+            package,
+            tree,
+            remote,
+            depth,
+        } => {
+            let package = PackageName::new(package)?;
+            let deps = library.depends(&package, remote, depth)?;
+            if format != OutputFormat::Text {
+                write_output(format, &deps)?;
+            } else if tree {
+                write_tree(&deps, "", "");
+            } else {
+                let list = deps.flatten();
+                for node in &list {
+                    println!(
+                        "{} ({})",
+                        node.name,
+                        PlainCallback::format_size(node.storage_size)
+                    );
+                }
+                let total: u64 = list.iter().map(|n| n.storage_size).sum();
+                println!(
+                    "Install size with dependencies: {}",
+                    PlainCallback::format_size(total + deps.storage_size)
+                );
+            }
+        }
+        Commands::Rdepends {
+            package,
+            tree,
+            depth,
+        } => {
+            let package = PackageName::new(package)?;
+            let deps = library.rdepends(&package, depth)?;
+            if format != OutputFormat::Text {
+                write_output(format, &deps)?;
+            } else if tree {
+                write_tree(&deps, "", "");
+            } else {
+                for node in deps.flatten() {
+                    println!("{}", node.name);
+                }
+            }
+        }
+        Commands::Graph {
+            installed: _,
+            remote,
+        } => {
+            let graph = match remote {
+                Some(packages) => {
+                    let packages = packages
+                        .into_iter()
+                        .map(PackageName::new)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    library.remote_graph(packages)?
+                }
+                None => library.get_package_state().get_dependency_graph(),
+            };
+            match format {
+                OutputFormat::Text | OutputFormat::Dot => print!("{}", graph.to_dot()),
+                _ => write_output(format, &graph)?,
+            }
+        }
+        Commands::Why { package } => {
+            let package = PackageName::new(package)?;
+            let paths = library.why(&package)?;
+            if format != OutputFormat::Text {
+                write_output(
+                    format,
+                    &WhyList {
+                        package: &package,
+                        paths,
+                    },
+                )?;
+            } else if paths.is_empty() {
+                println!("{package} is not needed by any manually installed package");
+            } else {
+                for path in paths {
+                    let path: Vec<&str> = path.iter().map(|p| p.as_str()).collect();
+                    println!("{}", path.join(" -> "));
+                }
+            }
         }
-        Commands::List => {
-            let packages = library.get_installed_packages()?;
-            for (i, name) in packages.iter().enumerate() {
-                write_package(i, name, color_support);
+        Commands::Verify { packages, repair } => {
+            let packages = packages
+                .into_iter()
+                .map(PackageName::new)
+                .collect::<Result<Vec<_>, _>>()?;
+            let issues = library.verify(packages)?;
+            if format == OutputFormat::Text {
+                write_issues(&issues);
+            } else {
+                write_output(format, &VerifyList { issues: &issues })?;
+            }
+
+            let damaged: BTreeSet<PackageName> =
+                issues.into_iter().filter_map(|i| i.package).collect();
+            if repair && !damaged.is_empty() {
+                if dry_run {
+                    for name in &damaged {
+                        println!("{name} would be repaired");
+                    }
+                } else {
+                    library.repair(damaged.into_iter().collect())?;
+                }
+            }
+        }
+        Commands::List { manual, auto } => {
+            let packages = if manual || auto {
+                library.get_marked_packages(manual)?
+            } else {
+                library.get_installed_packages()?
+            };
+            if format == OutputFormat::Text {
+                for (i, name) in packages.iter().enumerate() {
+                    write_package(i, name, color_support);
+                }
+            } else {
+                let state = library.get_package_state();
+                let packages = packages
+                    .iter()
+                    .filter_map(|name| {
+                        let state = state.installed.get(name)?;
+                        Some(InstalledPackage { name, state })
+                    })
+                    .collect();
+                write_output(format, &InstalledList { packages })?;
+            }
+        }
+        Commands::Mark { reason, packages } => {
+            let packages = process_packages(packages, library, false);
+            let manual = matches!(reason, MarkReason::Manual);
+            for name in library.mark(manual, packages)? {
+                match reason {
+                    MarkReason::Manual => println!("{name} marked as manually installed"),
+                    MarkReason::Auto => println!("{name} marked as automatically installed"),
+                }
+            }
+            needs_apply = true;
+        }
+        Commands::History { id } => {
+            let history = library.get_history()?;
+            match id {
+                Some(id) => {
+                    let Some(entry) = history.get(id) else {
+                        return Err(format!("transaction {id} not found").into());
+                    };
+                    if format == OutputFormat::Text {
+                        write_history_entry(entry);
+                    } else {
+                        write_output(format, entry)?;
+                    }
+                }
+                None if format == OutputFormat::Text => {
+                    for entry in &history.transactions {
+                        write_history_summary(entry);
+                    }
+                }
+                None => write_output(format, &history)?,
             }
         }
+        Commands::Rollback { id } => {
+            let id = library.rollback(id)?;
+            println!("Rolling back transaction {id}");
+            needs_apply = true;
+        }
+        Commands::Resume { rollback } => {
+            let Some(journal) = library.get_interrupted() else {
+                return Err("no interrupted transaction to resume".into());
+            };
+            if dry_run {
+                // rolling back goes from the new state to the old one
+                let entry = if rollback {
+                    println!("Interrupted transaction, to be rolled back");
+                    HistoryEntry::new(&journal.new_state, &journal.old_state)
+                } else {
+                    println!("Interrupted transaction");
+                    HistoryEntry::new(&journal.old_state, &journal.new_state)
+                };
+                write_history_packages(&entry.packages);
+                return Ok(());
+            }
+            let committed = library.resume(rollback)?;
+            if rollback {
+                println!("Interrupted transaction rolled back, {committed} changes committed");
+            } else {
+                println!("Interrupted transaction finished, {committed} changes committed");
+            }
+        }
+        Commands::Repo(_) => unreachable!("repo commands are executed before loading library"),
     }
 
-    if needs_apply {
+    if needs_apply && dry_run {
+        let plan = library.plan()?;
+        if format == OutputFormat::Text {
+            write_plan(&plan);
+        } else {
+            write_output(format, &plan)?;
+        }
+    } else if needs_apply {
         if let Err(e) = library.apply() {
             if let Err(e) = library.abort() {
                 eprintln!("Cannot aborting: {:#?}", e);
@@ -185,6 +935,189 @@ fn execute_command(
 
     Ok(())
 }
+fn execute_repo_command(
+    command: RepoCommands,
+    install_path: &Path,
+    cache_dir: Option<&Path>,
+    format: OutputFormat,
+    color_support: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        RepoCommands::Add { url } => {
+            let path = RepoManager::add_repo_config(install_path, &url)?;
+            println!("{} added to {}", url, path.display());
+        }
+        RepoCommands::Remove { repo } => {
+            let config = RepoManager::remove_repo_config(install_path, &repo)?;
+            println!("{} removed", config.name);
+        }
+        RepoCommands::Enable { repo } => {
+            let config = RepoManager::set_repo_enabled(install_path, &repo, true)?;
+            println!("{} enabled", config.name);
+        }
+        RepoCommands::Disable { repo } => {
+            let config = RepoManager::set_repo_enabled(install_path, &repo, false)?;
+            println!("{} disabled", config.name);
+        }
+        RepoCommands::List => {
+            let repos = RepoManager::read_repo_configs(install_path, cache_dir)?;
+            if format != OutputFormat::Text {
+                return write_output(format, &RepoList { repos });
+            }
+            for repo in &repos {
+                let status = if repo.enabled { "enabled" } else { "disabled" };
+                let key = if repo.pubkey_cached {
+                    "key cached"
+                } else {
+                    "key not cached"
+                };
+                if color_support && !repo.enabled {
+                    println!(
+                        "{}{} {} ({}, {}, priority {}){}",
+                        color::Fg(color::LightBlack),
+                        repo.name,
+                        repo.url,
+                        status,
+                        key,
+                        repo.priority,
+                        style::Reset
+                    );
+                } else {
+                    println!(
+                        "{} {} ({}, {}, priority {})",
+                        repo.name, repo.url, status, key, repo.priority
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_output<T: serde::Serialize>(
+    format: OutputFormat,
+    value: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    print!("{}", format_output(format, value)?);
+    Ok(())
+}
+
+fn format_output<T: serde::Serialize>(
+    format: OutputFormat,
+    value: &T,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Json => Ok(serde_json::to_string_pretty(value)? + "\n"),
+        OutputFormat::Toml => Ok(toml::to_string(value)?),
+        OutputFormat::Text => unreachable!("text output is written by each command"),
+        OutputFormat::Dot => Err("dot format is only supported by graph".into()),
+    }
+}
+
+fn write_history_summary(entry: &HistoryEntry) {
+    let count = |action| entry.packages.iter().filter(|p| p.action == action).count();
+    println!(
+        "{:>4} {} {:<7} +{} ~{} -{} {}",
+        entry.id,
+        format_time(entry.time),
+        format!("{:?}", entry.status).to_lowercase(),
+        count(PackageAction::Install),
+        count(PackageAction::Update),
+        count(PackageAction::Uninstall),
+        entry.command
+    );
+}
+
+fn write_history_entry(entry: &HistoryEntry) {
+    println!("Transaction {}", entry.id);
+    println!("  Time:      {}", format_time(entry.time));
+    println!("  Command:   {}", entry.command);
+    println!("  Status:    {:?}", entry.status);
+    println!("  Committed: {}", entry.committed);
+    if let Some(error) = &entry.error {
+        println!("  Error:     {}", error);
+    }
+    write_history_packages(&entry.packages);
+}
+
+fn write_history_packages(packages: &[HistoryPackage]) {
+    for p in packages {
+        match p.action {
+            PackageAction::Install => {
+                println!("  + {} {} ({})", p.name, p.new_version, p.new_blake3)
+            }
+            PackageAction::Update => println!(
+                "  ~ {} {} -> {} ({} -> {})",
+                p.name, p.old_version, p.new_version, p.old_blake3, p.new_blake3
+            ),
+            PackageAction::Uninstall => {
+                println!("  - {} {} ({})", p.name, p.old_version, p.old_blake3)
+            }
+        }
+    }
+}
+
+/// format seconds since unix epoch as UTC date and time
+fn format_time(secs: u64) -> String {
+    // days to civil date, from Howard Hinnant's date algorithms
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+fn write_tree(node: &DependencyTree, branch: &str, prefix: &str) {
+    let marker = if node.cycle {
+        " (cycle)"
+    } else if node.truncated {
+        " ..."
+    } else {
+        ""
+    };
+    println!("{branch}{}{marker}", node.name);
+    for (i, child) in node.children.iter().enumerate() {
+        if i + 1 == node.children.len() {
+            write_tree(child, &format!("{prefix}└── "), &format!("{prefix}    "));
+        } else {
+            write_tree(child, &format!("{prefix}├── "), &format!("{prefix}│   "));
+        }
+    }
+}
+
+fn write_issues(issues: &[VerifyIssue]) {
+    if issues.is_empty() {
+        println!("All files are intact");
+    }
+    for issue in issues {
+        let kind = match issue.kind {
+            VerifyIssueKind::Missing => "missing",
+            VerifyIssueKind::Modified => "modified",
+            VerifyIssueKind::WrongMode => "wrong mode",
+            VerifyIssueKind::Extra => "extra",
+            VerifyIssueKind::UnreadableHead => "unreadable",
+        };
+        match &issue.package {
+            Some(name) => println!("{:>10}: /{} ({})", kind, issue.path.display(), name),
+            None => println!("{:>10}: /{}", kind, issue.path.display()),
+        }
+    }
+}
+
 fn write_package(index: usize, name: &PackageName, color_support: bool) {
     if color_support {
         println!(
@@ -198,3 +1131,154 @@ fn write_package(index: usize, name: &PackageName, color_support: bool) {
         println!("{}: {}", index + 1, name);
     }
 }
+fn write_plan(plan: &TransactionPlan) {
+    if plan.packages.is_empty() {
+        println!("No package changes");
+    }
+    for (action, header, sign) in [
+        (PackageAction::Install, "Packages to install:", '+'),
+        (PackageAction::Update, "Packages to update:", '~'),
+        (PackageAction::Uninstall, "Packages to uninstall:", '-'),
+    ] {
+        let packages: Vec<_> = plan
+            .packages
+            .iter()
+            .filter(|p| p.action == action)
+            .collect();
+        if packages.is_empty() {
+            continue;
+        }
+        println!("{header}");
+        for p in packages {
+            let version = match action {
+                PackageAction::Install => p.new_version.clone(),
+                PackageAction::Update => format!("{} -> {}", p.old_version, p.new_version),
+                PackageAction::Uninstall => p.old_version.clone(),
+            };
+            println!(
+                "  {sign} {} {} ({}, {})",
+                p.name,
+                version,
+                p.remote,
+                PlainCallback::format_size(p.storage_size)
+            );
+        }
+    }
+    if !plan.list.held.is_empty() {
+        println!("Packages held back:");
+        for name in &plan.list.held {
+            println!("  = {name}");
+        }
+    }
+
+    println!();
+    println!(
+        "  Download size:  {}",
+        PlainCallback::format_size(plan.list.network_size)
+    );
+    println!(
+        "  Install size:   {}",
+        PlainCallback::format_size(plan.list.install_size)
+    );
+    println!(
+        "  Uninstall size: {}",
+        PlainCallback::format_size(plan.list.uninstall_size)
+    );
+
+    if !plan.conflicts.is_empty() {
+        println!("Predicted file conflicts:");
+        for c in &plan.conflicts {
+            println!(
+                "  -> {} (from {:?} replaced by {:?})",
+                c.path.display(),
+                c.former.as_ref().map(|p| p.as_str()).unwrap_or("?"),
+                c.newer.as_str(),
+            );
+        }
+    }
+    if !plan.unchecked.is_empty() {
+        println!("Conflicts not checked, archives are not downloaded yet:");
+        for name in &plan.unchecked {
+            println!("  ? {name}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_options() {
+        let args = Cli::try_parse_from([
+            "pkg",
+            "list",
+            "--root",
+            "/mnt/redox",
+            "--target",
+            "aarch64-unknown-redox",
+        ])
+        .unwrap();
+        assert_eq!(args.root, Some(PathBuf::from("/mnt/redox")));
+        assert_eq!(args.target.as_deref(), Some("aarch64-unknown-redox"));
+        assert!(matches!(args.command, Commands::List { .. }));
+
+        let args = Cli::try_parse_from(["pkg", "--root", "/mnt/redox", "list"]).unwrap();
+        assert_eq!(args.root, Some(PathBuf::from("/mnt/redox")));
+    }
+
+    #[test]
+    fn test_refuses_dry_run() {
+        let refuses = |args: &[&str]| {
+            let args = Cli::try_parse_from([&["pkg", "--dry-run"], args].concat()).unwrap();
+            assert!(args.dry_run);
+            args.command.refuses_dry_run()
+        };
+        assert!(refuses(&["clean"]));
+        assert!(refuses(&["download", "nano"]));
+        assert!(refuses(&["repo", "add", "https://static.redox-os.org/pkg"]));
+        assert!(refuses(&["repo", "disable", "redox"]));
+        assert!(!refuses(&["repo", "list"]));
+        assert!(!refuses(&["install", "nano"]));
+        assert!(!refuses(&["verify", "--repair"]));
+    }
+
+    #[test]
+    fn test_format_output() {
+        let nano = PackageName::new("nano").unwrap();
+        let state = InstallState {
+            remote: "static.redox-os.org".into(),
+            blake3: "abc".into(),
+            version: "8.0".into(),
+            manual: true,
+            storage_size: 10,
+            ..Default::default()
+        };
+        let list = InstalledList {
+            packages: vec![InstalledPackage {
+                name: &nano,
+                state: &state,
+            }],
+        };
+
+        let json = format_output(OutputFormat::Json, &list).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let package = &value["packages"][0];
+        assert_eq!(package["name"], "nano");
+        assert_eq!(package["remote"], "static.redox-os.org");
+        assert_eq!(package["manual"], true);
+        assert_eq!(package["storage_size"], 10);
+
+        let text = format_output(OutputFormat::Toml, &list).unwrap();
+        let value: toml::Value = toml::from_str(&text).unwrap();
+        assert_eq!(value["packages"][0]["name"].as_str(), Some("nano"));
+        assert_eq!(value["packages"][0]["version"].as_str(), Some("8.0"));
+
+        let plan = format_output(OutputFormat::Json, &TransactionPlan::default()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&plan).unwrap();
+        assert!(value["packages"].as_array().unwrap().is_empty());
+        assert!(value["conflicts"].as_array().unwrap().is_empty());
+
+        assert!(format_output(OutputFormat::Dot, &list).is_err());
+    }
+}
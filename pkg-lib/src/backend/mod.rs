@@ -2,11 +2,17 @@
 pub mod pkgar_backend;
 
 use std::io;
+#[cfg(feature = "library")]
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 use crate::{net_backend::DownloadError, package::PackageError, PackageName};
 #[cfg(feature = "library")]
-use crate::{package::RemotePackage, PackageState, Repository};
+use crate::{
+    package::{RemoteName, RemotePackage},
+    FileConflict, History, HistoryEntry, Journal, MergedRepository, PackageFileEntry, PackageState,
+    RepoPublicKey, VerifyIssue,
+};
 
 // todo: make this better
 #[derive(Error, Debug)]
@@ -21,6 +27,16 @@ pub enum Error {
     RepoCacheNotFound(PackageName),
     #[error("Public key for {0:?} is not available")]
     RepoNotLoaded(String),
+    #[error("Public key of repository {0:?} is not valid")]
+    RepoKeyInvalid(String),
+    #[error("Public key of repository {0:?} does not match its fingerprint")]
+    RepoKeyMismatch(String),
+    #[error("Public key of repository {0:?} can't be checked against its fingerprint")]
+    RepoKeyUnverifiable(String),
+    #[error("Repository {0:?} is already configured")]
+    RepoExists(String),
+    #[error("Repository {0:?} is not configured")]
+    RepoNotConfigured(String),
     #[error("Package {0:?} not found")]
     PackageNotFound(PackageName),
     #[error("Package {0:?} not installed")]
@@ -33,11 +49,39 @@ pub enum Error {
     PathIsNotValidUnicode(String),
     #[error("Content of {0:?} is not a valid UTF-8 content")]
     ContentIsNotValidUnicode(String),
+    #[error("Package database is locked by {0}")]
+    PackageLocked(String),
+    #[error("Package database is locked read-only")]
+    ReadOnly,
     #[error("You don't have permissions required for this action, try performing it as root")]
     MissingPermissions,
 
     #[error("Package {0:?} is protected")]
     ProtectedPackage(PackageName),
+    #[error("Archive {0:?} is not signed by a trusted key")]
+    ArchiveNotTrusted(String),
+    #[error("Package {0:?} is signed by a different key than other locally installed packages")]
+    LocalKeyMismatch(PackageName),
+    #[error("Package {0:?} is pinned to remote {1:?}")]
+    PackagePinned(PackageName, String),
+    #[error("Archive of {0:?} from remote {1:?} is signed by remote {2:?}")]
+    RemoteMismatch(PackageName, String, String),
+    #[error("Cache {0:?} may be shared with other installations, it can only be cleaned entirely")]
+    CacheShared(String),
+    #[error("Downloaded archive of {0:?} does not match the expected build")]
+    ArchiveHashMismatch(PackageName),
+    #[error("Transaction {0} not found in history")]
+    TransactionNotFound(u64),
+    #[error("Transaction {0} was not applied successfully")]
+    TransactionNotApplied(u64),
+    #[error("Previous build of {0:?} was not recorded in history")]
+    RollbackUnavailable(PackageName),
+    #[error("A previous transaction was interrupted, run `pkg resume` first")]
+    TransactionInterrupted,
+    #[error("No interrupted transaction to resume")]
+    NoInterruptedTransaction,
+    #[error("Installed build of {0:?} is no longer available from its repository")]
+    PackageBuildUnavailable(PackageName),
 
     #[error("IO error: {0}")]
     IO(io::Error),
@@ -71,20 +115,66 @@ impl From<std::io::Error> for Error {
 pub trait Backend {
     /// individually install a package
     fn install(&mut self, package: RemotePackage) -> Result<(), Error>;
-    /// individually uninstall a package
-    fn uninstall(&mut self, package: PackageName) -> Result<(), Error>;
+    /// open a package archive from a path to be installed under "local" remote,
+    /// checked with the public key or any trusted keys if not specified.
+    /// Metadata is read from a TOML file next to it or embedded in the archive
+    fn open_local_archive(
+        &mut self,
+        path: &Path,
+        pubkey: Option<RepoPublicKey>,
+    ) -> Result<RemotePackage, Error>;
+    /// individually uninstall a package, `force` allows uninstalling protected package
+    fn uninstall(&mut self, package: PackageName, force: bool) -> Result<(), Error>;
     /// individually upgrade a package
     fn upgrade(&mut self, package: &RemotePackage) -> Result<(), Error>;
+    /// download and verify a package archive and its TOML data into dest directory
+    fn download(&mut self, package: &RemotePackage, dest: &Path) -> Result<(), Error>;
+    /// override directory of downloaded files
+    fn set_cache_dir(&mut self, path: PathBuf);
+    /// remove downloaded files, or only which are not used by installed packages.
+    /// Only the latter is refused for a cache directory outside of the installation.
+    /// Returns count and total size of removed files.
+    fn clean_cache(&self, all: bool) -> Result<(usize, u64), Error>;
     /// download package TOML data
     fn get_package_detail(&self, package: &PackageName) -> Result<RemotePackage, Error>;
-    /// download repo TOML data
-    fn get_repository_detail(&self) -> Result<Repository, Error>;
+    /// download package TOML data from a specific remote
+    fn get_package_detail_from(
+        &self,
+        package: &PackageName,
+        remote: &RemoteName,
+    ) -> Result<RemotePackage, Error>;
+    /// remote a package is pinned to, if any
+    fn get_pin(&self, package: &PackageName) -> Option<RemoteName>;
+    /// download repo TOML data of every remote
+    fn get_repository_detail(&self) -> Result<MergedRepository, Error>;
     /// get state of current installation
     fn get_package_state(&self) -> PackageState;
+    /// list files owned by an installed package
+    fn get_package_files(&self, package: &PackageName) -> Result<Vec<PackageFileEntry>, Error>;
+    /// check installed files of packages against their heads
+    fn verify_packages(&self, packages: &[PackageName]) -> Result<Vec<VerifyIssue>, Error>;
+    /// predict file conflicts of changes from installed heads and already downloaded archives.
+    /// Returns conflicts and list of packages which archive is not downloaded yet.
+    fn predict_conflicts(
+        &self,
+        uninstall: &[PackageName],
+        install: &[RemotePackage],
+    ) -> Result<(Vec<FileConflict>, Vec<PackageName>), Error>;
     /// check if there's pending transaction conflicts before committing
     fn commit_check_conflict(&self) -> Result<&Vec<pkgar::TransactionConflict>, Error>;
     /// commit all pending changes, and set state of current installation
     fn commit_state(&mut self, new_state: PackageState) -> Result<usize, Error>;
+    /// set state of current installation without committing pending changes
+    fn set_package_state(&mut self, new_state: PackageState) -> Result<(), Error>;
+    /// read transaction history of current installation
+    fn get_history(&self) -> Result<History, Error>;
+    /// append a transaction to history of current installation, returns its id
+    fn append_history(&mut self, entry: HistoryEntry) -> Result<u64, Error>;
+    /// journal of a transaction which commit was interrupted, found on startup
+    fn get_interrupted(&self) -> Option<&Journal>;
+    /// finish committing the interrupted transaction, or revert it to the state before it.
+    /// Returns count of committed file changes.
+    fn resume(&mut self, roll_forward: bool) -> Result<usize, Error>;
     /// abort all pending changes
     fn abort_state(&mut self) -> Result<usize, Error>;
 }
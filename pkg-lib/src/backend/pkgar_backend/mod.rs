@@ -1,22 +1,39 @@
 use std::{
     cell::RefCell,
-    fs,
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
     path::{Path, PathBuf},
     rc::Rc,
+    time::Duration,
 };
 
-use pkgar::{MergedTransaction, PackageFile, Transaction};
-use pkgar_core::PublicKey;
+use pkgar::{ext::EntryExt, MergedTransaction, PackageFile, Transaction};
+use pkgar_core::{PackageSrc, PublicKey};
 
 use super::{Backend, Error};
 use crate::{
     callback::Callback,
-    package::{RemotePackage, Repository},
+    lock::{LockMode, PackageLock},
+    package::{RemoteName, RemotePackage, Repository},
     package_state::PackageState,
     repo_manager::RepoManager,
-    Package, PackageName, RepoPublicKeyFile,
+    FileConflict, History, HistoryEntry, Journal, JournalItem, MergedRepository, Package,
+    PackageAction, PackageError, PackageFileEntry, PackageName, RepoPublicKey, RepoPublicKeyFile,
+    VerifyIssue, VerifyIssueKind,
 };
 
+/// file type bits of pkgar entry mode
+const MODE_KIND: u32 = 0o170000;
+const MODE_SYMLINK: u32 = 0o120000;
+/// permission bits of pkgar entry mode
+const MODE_PERM: u32 = 0o7777;
+
+/// remote name of packages installed from an archive path
+pub(crate) const LOCAL_ARCHIVE_REMOTE: &str = "local";
+/// directory of package metadata embedded in archives, as `<name>.toml`
+const EMBEDDED_METADATA_DIR: &str = "usr/share/pkg";
+
 /// Package backend using pkgar
 pub struct PkgarBackend {
     /// Root path, usually "/"
@@ -28,21 +45,41 @@ pub struct PkgarBackend {
     /// temporary commit
     commits: Option<MergedTransaction>,
     keys_synced: bool,
+    /// archives opened from a path and their public key
+    local_archives: BTreeMap<PackageName, (PathBuf, PublicKey)>,
+    /// journal items of pending changes, heads are swapped after commit
+    pending: Vec<JournalItem>,
+    /// journal of a transaction which commit was interrupted
+    interrupted: Option<Journal>,
+    /// held until the backend is dropped
+    lock: PackageLock,
     callback: Rc<RefCell<dyn Callback>>,
 }
 
 impl PkgarBackend {
-    pub fn new<P: AsRef<Path>>(install_path: P, repo_manager: RepoManager) -> Result<Self, Error> {
+    /// Open an installation, locking its package database with `lock_mode`.
+    /// Waits up to `lock_wait` if the lock is held by another process.
+    pub fn new<P: AsRef<Path>>(
+        install_path: P,
+        repo_manager: RepoManager,
+        lock_mode: LockMode,
+        lock_wait: Option<Duration>,
+    ) -> Result<Self, Error> {
         let install_path = install_path.as_ref();
+        let callback = repo_manager.callback.clone();
 
-        let packages = PackageState::from_sysroot(install_path)?;
+        let lock = PackageLock::acquire(install_path, lock_mode, lock_wait, &callback)?;
 
-        // TODO: Use File::lock. This only checks permission
-        packages.to_sysroot(install_path)?;
+        let packages = PackageState::from_sysroot(install_path)?;
+        for (file, line) in &packages.protected_ignored {
+            callback.borrow_mut().protected_ignored(file, line);
+        }
 
-        fs::create_dir_all(install_path.join(crate::PACKAGES_HEAD_DIR))?;
+        if lock_mode == LockMode::Exclusive {
+            fs::create_dir_all(install_path.join(crate::PACKAGES_HEAD_DIR))?;
+        }
 
-        let callback = repo_manager.callback.clone();
+        let interrupted = Journal::from_sysroot(install_path)?;
 
         Ok(PkgarBackend {
             install_path: install_path.to_path_buf(),
@@ -51,6 +88,10 @@ impl PkgarBackend {
             // packages_lock,
             commits: Some(MergedTransaction::new()),
             keys_synced: false,
+            local_archives: BTreeMap::new(),
+            pending: Vec::new(),
+            interrupted,
+            lock,
             callback,
         })
     }
@@ -83,14 +124,293 @@ impl PkgarBackend {
         Ok(pkg)
     }
 
-    fn remove_package_head(&mut self, package: &PackageName) -> Result<(), Error> {
+    // locates a previously downloaded pkgar which hash still matches, without downloading it
+    fn get_cached_pkgar(&self, package: &RemotePackage) -> Option<(PathBuf, PublicKey)> {
+        let name = &package.package.name;
+        let remote = self.repo_manager.remote_map.get(&package.remote)?;
+        let pubkey = remote
+            .pubkey
+            .or_else(|| self.packages.pubkeys.get(&package.remote).map(|k| k.pkey))?;
+        if remote.is_local() {
+            let path = Path::new(&remote.path).join(format!("{name}.pkgar"));
+            return path.is_file().then_some((path, pubkey));
+        }
+
+        let blake3 = &package.package.blake3;
+        if blake3.is_empty() {
+            return None;
+        }
+        let path = self.repo_manager.get_cache_path(blake3);
+        let pkg = PackageFile::new(&path, &pubkey).ok()?;
+        if hex::encode(pkg.header().blake3) != *blake3 {
+            return None;
+        }
+        Some((path, pubkey))
+    }
+
+    // downloads a package or locates an opened local archive
+    fn fetch_pkgar(&self, package: &RemotePackage) -> Result<(PathBuf, PublicKey), Error> {
+        let name = &package.package.name;
+        if package.remote == LOCAL_ARCHIVE_REMOTE {
+            if let Some((path, pubkey)) = self.local_archives.get(name) {
+                return Ok((path.clone(), *pubkey));
+            }
+        }
+        if let Some(pin) = self.repo_manager.get_pin(name.as_str()) {
+            if *pin != package.remote {
+                return Err(Error::PackagePinned(name.clone(), pin.clone()));
+            }
+        }
+        if let Some(cached) = self.get_cached_pkgar(package) {
+            return Ok(cached);
+        }
+        let blake3 = &package.package.blake3;
+        let (local_path, repo) = self.repo_manager.get_package_pkgar(
+            name,
+            &package.remote,
+            blake3,
+            package.package.network_size,
+        )?;
+        let Some(pubkey) = repo.pubkey else {
+            return Err(Error::RepoNotLoaded(package.remote.clone()));
+        };
+        let discard = |path: &Path| -> Result<(), Error> {
+            if !repo.is_local() {
+                fs::remove_file(path)?;
+            }
+            Ok(())
+        };
+
+        // the archive must be signed by the remote which supplied its metadata
+        let pkg = match PackageFile::new(&local_path, &pubkey) {
+            Ok(pkg) => pkg,
+            Err(e) => {
+                let signer = self.find_signer(&local_path);
+                discard(&local_path)?;
+                return Err(match signer {
+                    Some(signer) => {
+                        Error::RemoteMismatch(name.clone(), package.remote.clone(), signer)
+                    }
+                    None => e.into(),
+                });
+            }
+        };
+        // repository might have published another build, the cache must stay content-addressed
+        if !blake3.is_empty() && hex::encode(pkg.header().blake3) != *blake3 {
+            discard(&local_path)?;
+            return Err(Error::ArchiveHashMismatch(name.clone()));
+        }
+        Ok((local_path, pubkey))
+    }
+
+    // finds a remote whose key signed an archive
+    fn find_signer(&self, path: &Path) -> Option<String> {
+        self.repo_manager
+            .remote_map
+            .values()
+            .find(|r| r.pubkey.is_some_and(|k| PackageFile::new(path, &k).is_ok()))
+            .map(|r| r.name.clone())
+    }
+
+    fn read_entry_paths(pkg: &mut PackageFile) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = Vec::new();
+        for entry in pkg.read_entries()? {
+            paths.push(entry.check_path()?.to_path_buf());
+        }
+        Ok(paths)
+    }
+
+    // picks metadata entry named after the archive, or the only one present
+    fn find_embedded_metadata(paths: &[PathBuf], archive: &Path) -> Option<usize> {
+        let candidates: Vec<usize> = paths
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| {
+                p.parent() == Some(Path::new(EMBEDDED_METADATA_DIR))
+                    && p.extension().is_some_and(|e| e == "toml")
+            })
+            .map(|(i, _)| i)
+            .collect();
+        candidates
+            .iter()
+            .copied()
+            .find(|&i| paths[i].file_stem() == archive.file_stem())
+            .or_else(|| (candidates.len() == 1).then(|| candidates[0]))
+    }
+
+    fn read_embedded_metadata(
+        pkg: &mut PackageFile,
+        archive: &Path,
+    ) -> Result<Option<Package>, Error> {
+        let entries = pkg.read_entries()?;
+        let paths = entries
+            .iter()
+            .map(|e| Ok(e.check_path()?.to_path_buf()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let Some(i) = Self::find_embedded_metadata(&paths, archive) else {
+            return Ok(None);
+        };
+
+        let entry = entries[i];
+        let mut data = vec![0; entry.size() as usize];
+        let mut read = 0;
+        while read < data.len() {
+            let count = pkg.read_entry(entry, read, &mut data[read..])?;
+            if count == 0 {
+                break;
+            }
+            read += count;
+        }
+        let path = paths[i].to_string_lossy().to_string();
+        if read < data.len() || blake3::hash(&data).as_bytes() != &entry.blake3() {
+            let name = paths[i]
+                .file_stem()
+                .and_then(|s| PackageName::try_from(s).ok())
+                .ok_or(Error::PackageNameInvalid(path))?;
+            return Err(Error::ArchiveHashMismatch(name));
+        }
+        let text = String::from_utf8(data).map_err(|_| Error::ContentIsNotValidUnicode(path))?;
+        Ok(Some(Package::from_toml(&text)?))
+    }
+
+    // compares an installed file with its entry in pkgar head
+    fn verify_file(&self, file: &PackageFileEntry) -> Result<Option<VerifyIssueKind>, Error> {
+        let path = self.install_path.join(&file.path);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Some(VerifyIssueKind::Missing))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let is_symlink = file.mode & MODE_KIND == MODE_SYMLINK;
+        if metadata.file_type().is_symlink() != is_symlink {
+            return Ok(Some(VerifyIssueKind::WrongMode));
+        }
+
+        // symlinks are stored in pkgar with its target as content
+        let mut hasher = blake3::Hasher::new();
+        if is_symlink {
+            hasher.update(fs::read_link(&path)?.as_os_str().as_bytes());
+        } else {
+            io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+        }
+        if hasher.finalize().to_hex().as_str() != file.blake3 {
+            return Ok(Some(VerifyIssueKind::Modified));
+        }
+
+        if !is_symlink && metadata.mode() & MODE_PERM != file.mode & MODE_PERM {
+            return Ok(Some(VerifyIssueKind::WrongMode));
+        }
+
+        Ok(None)
+    }
+
+    // checks files of packages against their heads, and looks for files not in `owned` next to them
+    fn verify_files(
+        &self,
+        files: &[(PackageName, Vec<PackageFileEntry>)],
+        owned: &BTreeSet<PathBuf>,
+    ) -> Result<Vec<VerifyIssue>, Error> {
+        let mut issues = Vec::new();
+        let mut dirs = BTreeSet::new();
+        for (name, files) in files {
+            for file in files {
+                if let Some(kind) = self.verify_file(file)? {
+                    issues.push(VerifyIssue {
+                        path: file.path.clone(),
+                        kind,
+                        package: Some(name.clone()),
+                    });
+                }
+                if let Some(parent) = file.path.parent() {
+                    dirs.insert(parent.to_path_buf());
+                }
+            }
+        }
+
+        // only look for extra files next to the verified ones
+        for dir in dirs {
+            let Ok(entries) = fs::read_dir(self.install_path.join(&dir)) else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let path = dir.join(entry.file_name());
+                if !owned.contains(&path) {
+                    issues.push(VerifyIssue {
+                        path,
+                        kind: VerifyIssueKind::Extra,
+                        package: None,
+                    });
+                }
+            }
+        }
+
+        Ok(issues)
+    }
+
+    // finds paths of incoming packages owned by another package, or existing without an owner.
+    // Files being replaced are expected to exist.
+    fn find_conflicts(
+        &self,
+        mut owners: BTreeMap<PathBuf, PackageName>,
+        replaced: &BTreeSet<PathBuf>,
+        incoming: Vec<(PackageName, Vec<PathBuf>)>,
+    ) -> Vec<FileConflict> {
+        let mut conflicts = Vec::new();
+        for (name, paths) in incoming {
+            for path in paths {
+                if let Some(former) = owners.get(&path) {
+                    if *former != name {
+                        conflicts.push(FileConflict {
+                            path: path.clone(),
+                            former: Some(former.clone()),
+                            newer: name.clone(),
+                        });
+                    }
+                } else if !replaced.contains(&path) && self.install_path.join(&path).exists() {
+                    conflicts.push(FileConflict {
+                        path: path.clone(),
+                        former: None,
+                        newer: name.clone(),
+                    });
+                }
+                owners.insert(path, name.clone());
+            }
+        }
+        conflicts
+    }
+
+    fn remove_package_head(&self, package: &PackageName) -> Result<(), Error> {
         let path = self
             .install_path
             .join(crate::PACKAGES_HEAD_DIR)
             .join(format!("{package}.pkgar_head"));
 
-        fs::remove_file(path)?;
-        Ok(())
+        match fs::remove_file(path) {
+            // already removed by an interrupted commit
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => Ok(r?),
+        }
+    }
+
+    // opens a head signed by any of the keys, it may be of either build during an interrupted commit
+    fn open_head(&self, package: &PackageName, pubkeys: &[PublicKey]) -> Option<PackageFile> {
+        let path = self
+            .install_path
+            .join(crate::PACKAGES_HEAD_DIR)
+            .join(format!("{package}.pkgar_head"));
+        if !path.is_file() {
+            return None;
+        }
+        pubkeys
+            .iter()
+            .find_map(|pubkey| PackageFile::new(&path, pubkey).ok())
     }
 
     fn create_head(
@@ -111,6 +431,56 @@ impl PkgarBackend {
         Ok(())
     }
 
+    fn check_exclusive(&self) -> Result<(), Error> {
+        if self.lock.mode() != LockMode::Exclusive {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    // archive and public key of the build a journal item installs
+    fn new_build(item: &JournalItem) -> Option<(PathBuf, PublicKey)> {
+        let path = item.archive.clone()?;
+        Some((path, item.pubkey.as_ref()?.pkey))
+    }
+
+    // commits pending changes, then swaps heads and writes the state.
+    // The journal must be written before, it's removed once everything is in place.
+    fn finish_commit(
+        &mut self,
+        new_state: PackageState,
+        heads: Vec<(PackageName, Option<(PathBuf, PublicKey)>)>,
+    ) -> Result<usize, Error> {
+        let mut transaction = self
+            .commits
+            .take()
+            .ok_or_else(|| Error::Pkgar(Box::new(pkgar::Error::DataNotInitialized)))?
+            .into_transaction();
+        self.callback
+            .borrow_mut()
+            .commit_start(transaction.pending_commit());
+        while transaction.pending_commit() > 0 {
+            self.callback.borrow_mut().commit_increment(&transaction);
+            if let Err(e) = transaction.commit_one() {
+                self.add_transaction(transaction, None);
+                return Err(Error::from(e));
+            }
+        }
+        self.callback.borrow_mut().commit_end();
+
+        for (package, build) in heads {
+            match build {
+                Some((path, pubkey)) => self.create_head(&path, &package, &pubkey)?,
+                None => self.remove_package_head(&package)?,
+            }
+        }
+
+        self.packages = new_state;
+        self.packages.to_sysroot(&self.install_path)?;
+        Journal::remove_from_sysroot(&self.install_path)?;
+        Ok(transaction.total_committed())
+    }
+
     fn sync_keys(&mut self) -> Result<(), Error> {
         if self.keys_synced {
             return Ok(());
@@ -119,7 +489,13 @@ impl PkgarBackend {
         for (name, map) in &mut self.repo_manager.remote_map {
             if map.pubkey.is_none() {
                 if let Some(pubk) = self.packages.pubkeys.get(name) {
-                    map.pubkey = Some(pubk.pkey)
+                    // an installed key must still match a configured fingerprint
+                    let trusted = map.pubkey_fingerprint.as_ref().is_none_or(|fingerprint| {
+                        RepoManager::check_fingerprint(name, &pubk.pkey, fingerprint).is_ok()
+                    });
+                    if trusted {
+                        map.pubkey = Some(pubk.pkey)
+                    }
                 }
             }
         }
@@ -137,20 +513,108 @@ impl Backend for PkgarBackend {
         if package.package.version.is_empty() {
             return Ok(()); // metapackage
         }
-        // TODO: Actually use that specific remote
-        let (local_path, repo) = self
-            .repo_manager
-            .get_package_pkgar(&package.package.name, package.package.network_size)?;
-        let mut pkg = PackageFile::new(&local_path, &repo.pubkey.unwrap())?;
+        let (local_path, pubkey) = self.fetch_pkgar(&package)?;
+        let mut pkg = PackageFile::new(&local_path, &pubkey)?;
         self.callback.borrow_mut().install_extract(&package);
         let install = Transaction::install(&mut pkg, &self.install_path)?;
-        self.create_head(&local_path, &package.package.name, &repo.pubkey.unwrap())?;
         self.add_transaction(install, Some(&pkg));
+        // an installed build is being extracted again, e.g. repaired
+        let old_blake3 = self
+            .packages
+            .installed
+            .get(&package.package.name)
+            .map(|state| state.blake3.clone())
+            .unwrap_or_default();
+        self.pending.push(JournalItem {
+            name: package.package.name.clone(),
+            action: if old_blake3.is_empty() {
+                PackageAction::Install
+            } else {
+                PackageAction::Update
+            },
+            archive: Some(local_path),
+            pubkey: Some(RepoPublicKeyFile::new(pubkey)),
+            old_blake3,
+            new_blake3: hex::encode(pkg.header().blake3),
+        });
         Ok(())
     }
 
-    fn uninstall(&mut self, package: PackageName) -> Result<(), Error> {
-        if self.packages.protected.contains(&package) {
+    fn open_local_archive(
+        &mut self,
+        path: &Path,
+        pubkey: Option<RepoPublicKey>,
+    ) -> Result<RemotePackage, Error> {
+        let keys: Vec<PublicKey> = match pubkey {
+            Some(pubkey) => vec![pubkey],
+            None => self
+                .packages
+                .pubkeys
+                .get(LOCAL_ARCHIVE_REMOTE)
+                .into_iter()
+                .chain(self.packages.pubkeys.values())
+                .map(|k| k.pkey)
+                .chain(
+                    self.repo_manager
+                        .remote_map
+                        .values()
+                        .filter_map(|r| r.pubkey),
+                )
+                .collect(),
+        };
+        let Some((mut pkg, pubkey)) = keys
+            .into_iter()
+            .find_map(|k| PackageFile::new(path, &k).ok().map(|pkg| (pkg, k)))
+        else {
+            return Err(Error::ArchiveNotTrusted(path.to_string_lossy().into()));
+        };
+
+        let toml_path = path.with_extension("toml");
+        let mut package = if toml_path.is_file() {
+            Package::from_file(&toml_path)?
+        } else if let Some(package) = Self::read_embedded_metadata(&mut pkg, path)? {
+            package
+        } else {
+            // no metadata, only enough to keep track of the package
+            let name = path
+                .file_stem()
+                .and_then(|s| PackageName::try_from(s).ok())
+                .ok_or_else(|| Error::PackageNameInvalid(path.to_string_lossy().into()))?;
+            let storage_size = pkg.read_entries()?.iter().map(|e| e.size()).sum();
+            Package {
+                name,
+                version: LOCAL_ARCHIVE_REMOTE.into(),
+                storage_size,
+                network_size: fs::metadata(path)?.len(),
+                ..Default::default()
+            }
+        };
+        if package.blake3.is_empty() {
+            package.blake3 = hex::encode(pkg.header().blake3);
+        }
+
+        // all local packages share one public key in packages.toml
+        let name = &package.name;
+        let local_key = self.packages.pubkeys.get(LOCAL_ARCHIVE_REMOTE);
+        let key_in_use = self.packages.installed.iter().any(|(n, s)| {
+            n != name
+                && s.remote == LOCAL_ARCHIVE_REMOTE
+                && local_key.is_some_and(|k| k.pkey != pubkey)
+        });
+        if key_in_use || self.local_archives.values().any(|(_, k)| *k != pubkey) {
+            return Err(Error::LocalKeyMismatch(name.clone()));
+        }
+
+        self.local_archives
+            .insert(name.clone(), (path.to_path_buf(), pubkey));
+        Ok(RemotePackage {
+            package,
+            remote: LOCAL_ARCHIVE_REMOTE.into(),
+        })
+    }
+
+    fn uninstall(&mut self, package: PackageName, force: bool) -> Result<(), Error> {
+        if !force && self.packages.is_protected(&package) {
             return Err(Error::ProtectedPackage(package));
         }
         self.sync_keys()?;
@@ -158,8 +622,14 @@ impl Backend for PkgarBackend {
         let mut pkg = self.get_package_head(&package)?;
         let remove = Transaction::remove(&mut pkg, &self.install_path)?;
         self.add_transaction(remove, Some(&pkg));
-
-        self.remove_package_head(&package)?;
+        self.pending.push(JournalItem {
+            old_blake3: hex::encode(pkg.header().blake3),
+            name: package,
+            action: PackageAction::Uninstall,
+            archive: None,
+            pubkey: None,
+            new_blake3: String::new(),
+        });
 
         Ok(())
     }
@@ -169,16 +639,76 @@ impl Backend for PkgarBackend {
 
         let name = &package.package.name;
         let mut pkg = self.get_package_head(name)?;
-        let (local_path, repo) = self
-            .repo_manager
-            .get_package_pkgar(name, package.package.network_size)?;
-        let mut pkg2 = PackageFile::new(&local_path, &repo.pubkey.unwrap())?;
+        let (local_path, pubkey) = self.fetch_pkgar(package)?;
+        let mut pkg2 = PackageFile::new(&local_path, &pubkey)?;
         let update = Transaction::replace(&mut pkg, &mut pkg2, &self.install_path)?;
-        self.create_head(&local_path, &name, &repo.pubkey.unwrap())?;
         self.add_transaction(update, Some(&pkg));
+        self.pending.push(JournalItem {
+            name: name.clone(),
+            action: PackageAction::Update,
+            archive: Some(local_path),
+            pubkey: Some(RepoPublicKeyFile::new(pubkey)),
+            old_blake3: hex::encode(pkg.header().blake3),
+            new_blake3: hex::encode(pkg2.header().blake3),
+        });
+        Ok(())
+    }
+
+    fn download(&mut self, package: &RemotePackage, dest: &Path) -> Result<(), Error> {
+        let name = &package.package.name;
+        fs::create_dir_all(dest)?;
+        // metadata is saved as published, unless it changed since
+        let toml = self
+            .repo_manager
+            .get_remote_toml(&package.remote, &format!("{name}.toml"))
+            .ok()
+            .flatten()
+            .filter(|toml| {
+                Package::from_toml(toml).is_ok_and(|p| p.blake3 == package.package.blake3)
+            })
+            .unwrap_or_else(|| package.package.to_toml());
+        fs::write(dest.join(format!("{name}.toml")), toml)?;
+        if package.package.version.is_empty() {
+            return Ok(()); // metapackage
+        }
+
+        self.sync_keys()?;
+        let (local_path, pubkey) = self.fetch_pkgar(package)?;
+        // checks signature
+        let mut pkg = PackageFile::new(&local_path, &pubkey)?;
+        Self::read_entry_paths(&mut pkg)?;
+        fs::copy(&local_path, dest.join(format!("{name}.pkgar")))?;
         Ok(())
     }
 
+    fn set_cache_dir(&mut self, path: PathBuf) {
+        self.repo_manager.set_download_path(path);
+    }
+
+    fn clean_cache(&self, all: bool) -> Result<(usize, u64), Error> {
+        self.check_exclusive()?;
+        if all {
+            return self.repo_manager.clean_cache(None);
+        }
+        // a cache outside of the installation may have archives used by other installations
+        let cache_dir = &self.repo_manager.download_path;
+        if *cache_dir != self.install_path.join(crate::PACKAGES_CACHE_DIR) {
+            return Err(Error::CacheShared(cache_dir.to_string_lossy().into()));
+        }
+        let mut keep: BTreeSet<String> = self
+            .packages
+            .installed
+            .values()
+            .map(|p| p.blake3.clone())
+            .collect();
+        // both builds may be needed to resume
+        for item in self.interrupted.iter().flat_map(|j| &j.items) {
+            keep.insert(item.old_blake3.clone());
+            keep.insert(item.new_blake3.clone());
+        }
+        self.repo_manager.clean_cache(Some(&keep))
+    }
+
     fn get_package_detail(&self, package: &PackageName) -> Result<RemotePackage, Error> {
         let (toml, remote) = self.repo_manager.get_package_toml(package)?;
 
@@ -188,18 +718,152 @@ impl Backend for PkgarBackend {
         })
     }
 
-    /// TODO: Multiple repository support
-    fn get_repository_detail(&self) -> Result<Repository, Error> {
-        let repo_str = PackageName::new("repo".to_string())?;
-        let (toml, _) = self.repo_manager.get_package_toml(&repo_str)?;
+    fn get_package_detail_from(
+        &self,
+        package: &PackageName,
+        remote: &RemoteName,
+    ) -> Result<RemotePackage, Error> {
+        let toml = self.repo_manager.get_package_toml_from(package, remote)?;
+
+        Ok(RemotePackage {
+            package: Package::from_toml(&toml)?,
+            remote: remote.clone(),
+        })
+    }
+
+    fn get_pin(&self, package: &PackageName) -> Option<RemoteName> {
+        self.repo_manager.get_pin(package.as_str()).cloned()
+    }
+
+    fn get_repository_detail(&self) -> Result<MergedRepository, Error> {
+        let mut remotes = Vec::new();
+        for remote in self.repo_manager.search_order() {
+            if let Some(toml) = self.repo_manager.get_remote_toml(remote, "repo.toml")? {
+                remotes.push((remote.clone(), Repository::from_toml(&toml)?));
+            }
+        }
+        if remotes.is_empty() {
+            let repo_str = PackageName::new("repo".to_string())?;
+            return Err(PackageError::PackageNotFound(repo_str).into());
+        }
 
-        Ok(Repository::from_toml(&toml)?)
+        let mut merged = MergedRepository::new(remotes);
+        // pinned packages are only offered by their remote
+        merged.packages.retain(|name, builds| {
+            if let Some(pin) = self.repo_manager.get_pin(name) {
+                builds.retain(|build| build.remote == *pin);
+            }
+            !builds.is_empty()
+        });
+        Ok(merged)
     }
 
     fn get_package_state(&self) -> PackageState {
         self.packages.clone()
     }
 
+    fn get_package_files(&self, package: &PackageName) -> Result<Vec<PackageFileEntry>, Error> {
+        if self
+            .packages
+            .installed
+            .get(package)
+            .is_some_and(|p| p.blake3.is_empty())
+        {
+            return Ok(Vec::new()); // metapackage
+        }
+        let mut pkg = self.get_package_head(package)?;
+        let mut files = Vec::new();
+        for entry in pkg.read_entries()? {
+            files.push(PackageFileEntry {
+                path: entry.check_path()?.to_path_buf(),
+                mode: entry.mode().map_err(pkgar::Error::from)?.bits(),
+                size: entry.size(),
+                blake3: hex::encode(entry.blake3()),
+            });
+        }
+        Ok(files)
+    }
+
+    fn verify_packages(&self, packages: &[PackageName]) -> Result<Vec<VerifyIssue>, Error> {
+        // a missing or corrupt head is reported for its package, the others are still checked
+        let mut owned = BTreeSet::new();
+        let mut heads: BTreeMap<&PackageName, Option<Vec<PackageFileEntry>>> = BTreeMap::new();
+        for name in self.packages.installed.keys() {
+            match self.get_package_files(name) {
+                Ok(files) => {
+                    owned.extend(files.iter().map(|f| f.path.clone()));
+                    heads.insert(name, Some(files));
+                }
+                Err(_) => {
+                    heads.insert(name, None);
+                }
+            }
+        }
+
+        let mut issues = Vec::new();
+        let mut files = Vec::new();
+        for name in packages {
+            match heads.get(name) {
+                Some(Some(head)) => files.push((name.clone(), head.clone())),
+                Some(None) => issues.push(VerifyIssue {
+                    path: Path::new(crate::PACKAGES_HEAD_DIR).join(format!("{name}.pkgar_head")),
+                    kind: VerifyIssueKind::UnreadableHead,
+                    package: Some(name.clone()),
+                }),
+                None => return Err(Error::PackageNotInstalled(name.clone())),
+            }
+        }
+        issues.extend(self.verify_files(&files, &owned)?);
+        Ok(issues)
+    }
+    fn predict_conflicts(
+        &self,
+        uninstall: &[PackageName],
+        install: &[RemotePackage],
+    ) -> Result<(Vec<FileConflict>, Vec<PackageName>), Error> {
+        let changed: BTreeSet<&PackageName> = uninstall
+            .iter()
+            .chain(install.iter().map(|p| &p.package.name))
+            .collect();
+
+        // files owned by packages that stays and files going to be replaced
+        let mut owners: BTreeMap<PathBuf, PackageName> = BTreeMap::new();
+        let mut replaced = BTreeSet::new();
+        for name in self.packages.installed.keys() {
+            let Ok(mut head) = self.get_package_head(name) else {
+                continue;
+            };
+            let paths = Self::read_entry_paths(&mut head)?;
+            if changed.contains(name) {
+                replaced.extend(paths);
+            } else {
+                for path in paths {
+                    owners.insert(path, name.clone());
+                }
+            }
+        }
+
+        let mut incoming = Vec::new();
+        let mut unchecked = Vec::new();
+        for package in install {
+            let name = &package.package.name;
+            if package.package.version.is_empty() {
+                continue; // metapackage
+            }
+            let Some(mut pkg) = self
+                .get_cached_pkgar(package)
+                .and_then(|(path, pubkey)| PackageFile::new(&path, &pubkey).ok())
+            else {
+                unchecked.push(name.clone());
+                continue;
+            };
+            incoming.push((name.clone(), Self::read_entry_paths(&mut pkg)?));
+        }
+
+        let conflicts = self.find_conflicts(owners, &replaced, incoming);
+        Ok((conflicts, unchecked))
+    }
+
     fn commit_check_conflict(&self) -> Result<&Vec<pkgar::TransactionConflict>, Error> {
         let transaction = self
             .commits
@@ -208,34 +872,146 @@ impl Backend for PkgarBackend {
         Ok(transaction.get_possible_conflicts())
     }
 
-    fn commit_state(&mut self, new_state: PackageState) -> Result<usize, Error> {
-        let mut transaction = self
-            .commits
-            .take()
-            .ok_or_else(|| Error::Pkgar(Box::new(pkgar::Error::DataNotInitialized)))?
-            .into_transaction();
-        self.callback
-            .borrow_mut()
-            .commit_start(transaction.pending_commit());
-        while transaction.pending_commit() > 0 {
-            self.callback.borrow_mut().commit_increment(&transaction);
-            if let Err(e) = transaction.commit_one() {
-                self.add_transaction(transaction, None);
-                return Err(Error::from(e));
-            }
+    fn commit_state(&mut self, mut new_state: PackageState) -> Result<usize, Error> {
+        self.check_exclusive()?;
+        // the journal on disk is the only record to resume it
+        if self.interrupted.is_some() {
+            return Err(Error::TransactionInterrupted);
         }
-        self.callback.borrow_mut().commit_end();
-
-        self.packages = new_state;
         for (k, v) in &self.repo_manager.remote_map {
             let Some(pubkey) = v.pubkey else {
                 return Err(Error::RepoNotLoaded(k.to_string()));
             };
             let pk = RepoPublicKeyFile::new(pubkey);
-            self.packages.pubkeys.insert(k.to_string(), pk);
+            new_state.pubkeys.insert(k.to_string(), pk);
         }
+        if let Some((_, pubkey)) = self.local_archives.values().next() {
+            let pk = RepoPublicKeyFile::new(*pubkey);
+            new_state
+                .pubkeys
+                .insert(LOCAL_ARCHIVE_REMOTE.to_string(), pk);
+        }
+
+        let journal = Journal {
+            old_state: self.packages.clone(),
+            new_state,
+            items: std::mem::take(&mut self.pending),
+        };
+        journal.to_sysroot(&self.install_path)?;
+
+        let heads = journal
+            .items
+            .iter()
+            .map(|item| (item.name.clone(), Self::new_build(item)))
+            .collect();
+        let committed = self.finish_commit(journal.new_state, heads)?;
+        self.local_archives.clear();
+        Ok(committed)
+    }
+
+    fn set_package_state(&mut self, new_state: PackageState) -> Result<(), Error> {
+        self.check_exclusive()?;
+        self.packages = new_state;
         self.packages.to_sysroot(&self.install_path)?;
-        Ok(transaction.total_committed())
+        Ok(())
+    }
+
+    fn get_history(&self) -> Result<History, Error> {
+        Ok(History::from_sysroot(&self.install_path)?)
+    }
+
+    fn append_history(&mut self, entry: HistoryEntry) -> Result<u64, Error> {
+        self.check_exclusive()?;
+        Ok(History::append_to_sysroot(&self.install_path, entry)?)
+    }
+
+    fn get_interrupted(&self) -> Option<&Journal> {
+        self.interrupted.as_ref()
+    }
+
+    fn resume(&mut self, roll_forward: bool) -> Result<usize, Error> {
+        let Some(journal) = self.interrupted.clone() else {
+            return Err(Error::NoInterruptedTransaction);
+        };
+        self.check_exclusive()?;
+
+        // the journal describes whole transaction, discard anything pending
+        self.commits = Some(MergedTransaction::new());
+        self.pending.clear();
+        let mut heads = Vec::new();
+        // files of any build involved, and files of the builds to be in place
+        let mut stale = BTreeSet::new();
+        let mut kept = BTreeSet::new();
+        for item in &journal.items {
+            let new_build = Self::new_build(item);
+            let state_key = |state: &PackageState| {
+                let remote = &state.installed.get(&item.name)?.remote;
+                state.pubkeys.get(remote).map(|k| k.pkey)
+            };
+            let old_key = state_key(&journal.old_state);
+            let head_keys: Vec<PublicKey> = old_key
+                .into_iter()
+                .chain(new_build.as_ref().map(|(_, k)| *k))
+                .collect();
+
+            // the build to be in place after resuming
+            // packages installed before are restored, whatever the journal item says
+            let target = if roll_forward {
+                new_build.clone()
+            } else if let Some(old) = journal.old_state.installed.get(&item.name) {
+                let package = RemotePackage {
+                    package: Package {
+                        name: item.name.clone(),
+                        version: old.version.clone(),
+                        blake3: old.blake3.clone(),
+                        ..Default::default()
+                    },
+                    remote: old.remote.clone(),
+                };
+                let cached = self
+                    .get_cached_pkgar(&package)
+                    .map(|(path, key)| (path, old_key.unwrap_or(key)));
+                Some(cached.ok_or_else(|| Error::RollbackUnavailable(item.name.clone()))?)
+            } else {
+                None
+            };
+            // files on disk may be of either build, and the commit could have
+            // written any file of the new archive
+            let new_archive = new_build
+                .as_ref()
+                .and_then(|(path, pubkey)| PackageFile::new(path, pubkey).ok());
+            let head = self.open_head(&item.name, &head_keys);
+            for mut source in new_archive.into_iter().chain(head) {
+                stale.extend(Self::read_entry_paths(&mut source)?);
+            }
+
+            if let Some((path, pubkey)) = &target {
+                let mut target_pkg = PackageFile::new(path, pubkey)?;
+                kept.extend(Self::read_entry_paths(&mut target_pkg)?);
+                // overwrites whichever build is in place
+                let transaction = Transaction::install(&mut target_pkg, &self.install_path)?;
+                self.add_transaction(transaction, Some(&target_pkg));
+            }
+            heads.push((item.name.clone(), target));
+        }
+
+        // removed directly, some may be already removed before the interruption
+        for path in stale.difference(&kept) {
+            match fs::remove_file(self.install_path.join(path)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        let mut state = if roll_forward {
+            journal.new_state
+        } else {
+            journal.old_state
+        };
+        state.protected_drop_in = self.packages.protected_drop_in.clone();
+        let committed = self.finish_commit(state, heads)?;
+        self.interrupted = None;
+        Ok(committed)
     }
 
     fn abort_state(&mut self) -> Result<usize, Error> {
@@ -255,6 +1031,307 @@ impl Backend for PkgarBackend {
             }
         }
         self.callback.borrow_mut().abort_end();
+        // nothing is changed, heads are only swapped after commit
+        self.pending.clear();
+        if self.interrupted.is_none() {
+            Journal::remove_from_sysroot(&self.install_path)?;
+        }
         Ok(transaction.total_committed())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{callback::SilentCallback, net_backend::CurlBackend, net_backend::DownloadBackend};
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mock_backend(sysroot: &Path) -> PkgarBackend {
+        let _ = fs::remove_dir_all(sysroot);
+        open_backend(sysroot)
+    }
+
+    fn open_backend(sysroot: &Path) -> PkgarBackend {
+        let callback = Rc::new(RefCell::new(SilentCallback::new()));
+        let mut repo_manager = RepoManager::new(callback, Box::new(CurlBackend::new().unwrap()));
+        repo_manager.set_download_path(sysroot.join(crate::PACKAGES_CACHE_DIR));
+        PkgarBackend::new(sysroot, repo_manager, LockMode::Exclusive, None).unwrap()
+    }
+
+    #[test]
+    fn test_download_metadata() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_download_metadata");
+        let _ = fs::remove_dir_all(&sysroot);
+        let source = sysroot.join("source");
+        fs::create_dir_all(source.join("x86_64-unknown-redox"))?;
+        RepoPublicKeyFile::new([1; 32]).save(source.join("id_ed25519.pub.toml"))?;
+        let toml = "# editors\nname = \"editors\"\ndepends = [\"nano\", \"vim\"]\n";
+        fs::write(source.join("x86_64-unknown-redox/editors.toml"), toml)?;
+
+        let callback = Rc::new(RefCell::new(SilentCallback::new()));
+        let mut repo_manager = RepoManager::new(callback, Box::new(CurlBackend::new().unwrap()));
+        repo_manager.set_download_path(sysroot.join(crate::PACKAGES_CACHE_DIR));
+        repo_manager.add_local(
+            "source",
+            &source.to_string_lossy(),
+            "x86_64-unknown-redox",
+            &source,
+        )?;
+        let mut backend = PkgarBackend::new(
+            sysroot.join("root"),
+            repo_manager,
+            LockMode::Exclusive,
+            None,
+        )?;
+
+        let name = PackageName::new("editors").unwrap();
+        let package = backend.get_package_detail(&name)?;
+        let dest = sysroot.join("dest");
+        backend.download(&package, &dest)?;
+        assert_eq!(fs::read_to_string(dest.join("editors.toml"))?, toml);
+
+        drop(backend);
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_interrupted() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_commit_interrupted");
+        drop(mock_backend(&sysroot));
+        let journal = Journal {
+            items: vec![JournalItem {
+                name: PackageName::new("nano").unwrap(),
+                action: PackageAction::Install,
+                archive: None,
+                pubkey: None,
+                old_blake3: String::new(),
+                new_blake3: "abc".into(),
+            }],
+            ..Default::default()
+        };
+        journal.to_sysroot(&sysroot)?;
+
+        let mut backend = open_backend(&sysroot);
+        assert!(backend.get_interrupted().is_some());
+        assert!(matches!(
+            backend.commit_state(PackageState::default()),
+            Err(Error::TransactionInterrupted)
+        ));
+        let kept = Journal::from_sysroot(&sysroot)?.unwrap();
+        assert_eq!(kept.items[0].new_blake3, "abc");
+
+        drop(backend);
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clean_shared_cache() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_clean_shared_cache");
+        let mut backend = mock_backend(&sysroot);
+        assert_eq!(backend.clean_cache(false)?, (0, 0));
+
+        backend.set_cache_dir(sysroot.join("shared"));
+        assert!(matches!(
+            backend.clean_cache(false),
+            Err(Error::CacheShared(_))
+        ));
+        assert_eq!(backend.clean_cache(true)?, (0, 0));
+
+        drop(backend);
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_embedded_metadata() {
+        let paths = |list: &[&str]| list.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let find = PkgarBackend::find_embedded_metadata;
+        let archive = Path::new("/tmp/nano.pkgar");
+
+        assert_eq!(find(&paths(&["usr/bin/nano"]), archive), None);
+        assert_eq!(
+            find(
+                &paths(&["usr/bin/nano", "usr/share/pkg/editor.toml"]),
+                archive
+            ),
+            Some(1)
+        );
+        // named after the archive when several are present
+        assert_eq!(
+            find(
+                &paths(&["usr/share/pkg/a.toml", "usr/share/pkg/nano.toml"]),
+                archive
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            find(
+                &paths(&["usr/share/pkg/a.toml", "usr/share/pkg/b.toml"]),
+                archive
+            ),
+            None
+        );
+        assert_eq!(
+            find(
+                &paths(&["usr/share/pkg/nano.txt", "usr/share/pkg/doc/nano.toml"]),
+                archive
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_verify_files() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_verify_files");
+        let backend = mock_backend(&sysroot);
+        let bin = sysroot.join("usr/bin");
+        fs::create_dir_all(&bin)?;
+
+        let entry = |path: &str, content: &[u8], mode: u32| PackageFileEntry {
+            path: PathBuf::from(path),
+            mode,
+            size: content.len() as u64,
+            blake3: blake3::hash(content).to_hex().as_str().to_string(),
+        };
+        let files = vec![
+            entry("usr/bin/intact", b"intact", 0o100755),
+            entry("usr/bin/modified", b"original", 0o100755),
+            entry("usr/bin/missing", b"missing", 0o100755),
+            entry("usr/bin/chmod", b"chmod", 0o100755),
+            entry("usr/bin/link", b"intact", 0o120777),
+        ];
+        for (name, content) in [
+            ("intact", &b"intact"[..]),
+            ("modified", b"changed"),
+            ("chmod", b"chmod"),
+            ("stray", b"stray"),
+        ] {
+            fs::write(bin.join(name), content)?;
+            fs::set_permissions(bin.join(name), fs::Permissions::from_mode(0o755))?;
+        }
+        fs::set_permissions(bin.join("chmod"), fs::Permissions::from_mode(0o644))?;
+        std::os::unix::fs::symlink("intact", bin.join("link"))?;
+
+        let kind = |i: usize| backend.verify_file(&files[i]);
+        assert_eq!(kind(0)?, None);
+        assert_eq!(kind(1)?, Some(VerifyIssueKind::Modified));
+        assert_eq!(kind(2)?, Some(VerifyIssueKind::Missing));
+        assert_eq!(kind(3)?, Some(VerifyIssueKind::WrongMode));
+        assert_eq!(kind(4)?, None);
+
+        let name = PackageName::new("test").unwrap();
+        let owned = files.iter().map(|f| f.path.clone()).collect();
+        let mut issues: Vec<_> = backend
+            .verify_files(&[(name.clone(), files)], &owned)?
+            .into_iter()
+            .map(|i| (i.path, i.kind, i.package))
+            .collect();
+        issues.sort();
+        let path = PathBuf::from;
+        assert_eq!(
+            issues,
+            vec![
+                (
+                    path("usr/bin/chmod"),
+                    VerifyIssueKind::WrongMode,
+                    Some(name.clone())
+                ),
+                (
+                    path("usr/bin/missing"),
+                    VerifyIssueKind::Missing,
+                    Some(name.clone())
+                ),
+                (
+                    path("usr/bin/modified"),
+                    VerifyIssueKind::Modified,
+                    Some(name.clone())
+                ),
+                (path("usr/bin/stray"), VerifyIssueKind::Extra, None),
+            ]
+        );
+
+        drop(backend);
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_unreadable_head() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_verify_unreadable_head");
+        let mut backend = mock_backend(&sysroot);
+        let name = PackageName::new("test").unwrap();
+        backend.packages.installed.insert(
+            name.clone(),
+            crate::InstallState {
+                blake3: "missing".into(),
+                ..Default::default()
+            },
+        );
+
+        let issues = backend.verify_packages(&[name.clone()])?;
+        assert_eq!(
+            issues,
+            vec![VerifyIssue {
+                path: PathBuf::from("var/lib/packages/test.pkgar_head"),
+                kind: VerifyIssueKind::UnreadableHead,
+                package: Some(name),
+            }]
+        );
+        assert!(matches!(
+            backend.verify_packages(&[PackageName::new("other").unwrap()]),
+            Err(Error::PackageNotInstalled(_))
+        ));
+
+        drop(backend);
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_conflicts() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_find_conflicts");
+        let backend = mock_backend(&sysroot);
+        fs::create_dir_all(sysroot.join("usr/bin"))?;
+        for file in ["usr/bin/nano", "usr/bin/vim", "usr/bin/stray"] {
+            fs::write(sysroot.join(file), "")?;
+        }
+        let name = |n| PackageName::new(n).unwrap();
+        let paths = |list: &[&str]| list.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        // vim stays installed, nano is upgraded
+        let owners = BTreeMap::from([(PathBuf::from("usr/bin/vim"), name("vim"))]);
+        let replaced = BTreeSet::from([PathBuf::from("usr/bin/nano")]);
+        let conflicts = backend.find_conflicts(
+            owners,
+            &replaced,
+            vec![
+                (name("nano"), paths(&["usr/bin/nano"])),
+                (name("ed"), paths(&["usr/bin/ed", "usr/share/editor"])),
+                (name("vi"), paths(&["usr/bin/vim", "usr/share/editor"])),
+                (name("junk"), paths(&["usr/bin/stray"])),
+            ],
+        );
+        let conflicts: Vec<_> = conflicts
+            .into_iter()
+            .map(|c| (c.path, c.former, c.newer))
+            .collect();
+        assert_eq!(
+            conflicts,
+            vec![
+                (PathBuf::from("usr/bin/vim"), Some(name("vim")), name("vi")),
+                (
+                    PathBuf::from("usr/share/editor"),
+                    Some(name("ed")),
+                    name("vi")
+                ),
+                (PathBuf::from("usr/bin/stray"), None, name("junk")),
+            ]
+        );
+
+        drop(backend);
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+}
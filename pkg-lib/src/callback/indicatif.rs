@@ -93,6 +93,11 @@ impl Callback for IndicatifCallback {
         self.pb.suspend(|| self.fallback.install_prompt(list))
     }
 
+    fn uninstall_protected_prompt(&mut self, list: &[crate::PackageName]) -> Result<(), Error> {
+        self.pb
+            .suspend(|| self.fallback.uninstall_protected_prompt(list))
+    }
+
     fn install_check_conflict(
         &mut self,
         list: &Vec<pkgar::TransactionConflict>,
@@ -107,6 +112,24 @@ impl Callback for IndicatifCallback {
         });
     }
 
+    fn lock_wait(&mut self, holder: &str) {
+        self.pb.suspend(|| {
+            self.fallback.lock_wait(holder);
+        });
+    }
+
+    fn protected_ignored(&mut self, file: &std::path::Path, line: &str) {
+        self.pb.suspend(|| {
+            self.fallback.protected_ignored(file, line);
+        });
+    }
+
+    fn update_remote_missing(&mut self, pkg_name: &crate::PackageName, remote: &str) {
+        self.pb.suspend(|| {
+            self.fallback.update_remote_missing(pkg_name, remote);
+        });
+    }
+
     fn download_start(&mut self, length: u64, file: &str) {
         self.unknown_len = length == 0;
         if self.unknown_len {
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use crate::{package::RemotePackage, PackageName};
 
 #[cfg(all(feature = "indicatif", feature = "library"))]
@@ -21,12 +23,23 @@ pub trait Callback {
     #[cfg(feature = "library")]
     fn install_prompt(&mut self, list: &PackageList) -> Result<(), Error>;
     #[cfg(feature = "library")]
+    fn uninstall_protected_prompt(&mut self, list: &[PackageName]) -> Result<(), Error>;
+    #[cfg(feature = "library")]
     fn install_check_conflict(
         &mut self,
         list: &Vec<pkgar::TransactionConflict>,
     ) -> Result<(), Error>;
     fn install_extract(&mut self, pkg_name: &RemotePackage);
 
+    /// package database is locked by holder, waiting for it to be released
+    fn lock_wait(&mut self, holder: &str);
+
+    /// line of a protected.d file is not a package name, so it protects nothing
+    fn protected_ignored(&mut self, file: &Path, line: &str);
+
+    /// package is not updated, the remote it was installed from is no longer configured
+    fn update_remote_missing(&mut self, pkg_name: &PackageName, remote: &str);
+
     fn download_start(&mut self, length: u64, file: &str);
     fn download_increment(&mut self, downloaded: u64);
     fn download_end(&mut self);
@@ -153,6 +153,13 @@ impl Callback for PlainCallback {
             }
         }
 
+        if !list.held.is_empty() {
+            eprintln!("Packages held back:");
+            for pkg in &list.held {
+                eprintln!("  = {}", pkg);
+            }
+        }
+
         eprintln!();
         if list.network_size > 0 {
             eprintln!("  Download size:  {}", Self::format_size(list.network_size));
@@ -170,6 +177,34 @@ impl Callback for PlainCallback {
         self.confirm_transaction()
     }
 
+    #[cfg(feature = "library")]
+    fn uninstall_protected_prompt(&mut self, list: &[crate::PackageName]) -> Result<(), Error> {
+        eprintln!("\nWARNING: The following packages are protected:");
+        for pkg in list {
+            eprintln!("  ! {}", pkg);
+        }
+        eprintln!("Removing them may leave the system unusable.");
+
+        if !self.interactive {
+            return Err(Error::ProtectedPackage(list[0].clone()));
+        }
+
+        eprint!("Type 'yes' to remove protected packages: ");
+        self.flush();
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap_or(0);
+        if input.trim() != "yes" {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "Removal of protected packages aborted by user",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
     #[cfg(feature = "library")]
     fn install_check_conflict(
         &mut self,
@@ -197,6 +232,24 @@ impl Callback for PlainCallback {
         self.flush();
     }
 
+    fn lock_wait(&mut self, holder: &str) {
+        eprintln!("Waiting for package database locked by {holder}...");
+        self.flush();
+    }
+
+    fn protected_ignored(&mut self, file: &std::path::Path, line: &str) {
+        eprintln!(
+            "Ignoring {line:?} in {}: not a package name",
+            file.display()
+        );
+        self.flush();
+    }
+
+    fn update_remote_missing(&mut self, pkg_name: &crate::PackageName, remote: &str) {
+        eprintln!("Not updating {pkg_name}, its remote {remote} is no longer configured");
+        self.flush();
+    }
+
     fn download_start(&mut self, length: u64, file: &str) {
         self.size = length;
         self.unknown_size = length == 0;
@@ -26,6 +26,11 @@ impl Callback for SilentCallback {
         Ok(())
     }
 
+    #[cfg(feature = "library")]
+    fn uninstall_protected_prompt(&mut self, _: &[crate::PackageName]) -> Result<(), Error> {
+        Ok(())
+    }
+
     #[cfg(feature = "library")]
     fn install_check_conflict(&mut self, _: &Vec<pkgar::TransactionConflict>) -> Result<(), Error> {
         Ok(())
@@ -33,6 +38,12 @@ impl Callback for SilentCallback {
 
     fn install_extract(&mut self, _: &RemotePackage) {}
 
+    fn lock_wait(&mut self, _: &str) {}
+
+    fn protected_ignored(&mut self, _: &std::path::Path, _: &str) {}
+
+    fn update_remote_missing(&mut self, _: &crate::PackageName, _: &str) {}
+
     fn download_start(&mut self, _: u64, _: &str) {}
 
     fn download_increment(&mut self, _: u64) {}
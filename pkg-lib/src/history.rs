@@ -0,0 +1,198 @@
+use std::{
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{InstallState, PackageAction, PackageError, PackageName, PackageState};
+
+/// Outcome of a transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+    Success,
+    Aborted,
+}
+
+/// A package changed by a transaction
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryPackage {
+    pub name: PackageName,
+    pub action: PackageAction,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub old_version: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub new_version: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub old_blake3: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub new_blake3: String,
+    /// install state before the transaction, used to roll back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_state: Option<InstallState>,
+}
+
+/// A transaction applied to an installation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub id: u64,
+    /// seconds since unix epoch
+    pub time: u64,
+    /// command line which started the transaction
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub command: String,
+    pub status: TransactionStatus,
+    /// count of committed file changes
+    pub committed: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<HistoryPackage>,
+}
+
+impl HistoryEntry {
+    /// Describe changes from old to new state, as a successful transaction done now
+    pub fn new(old: &PackageState, new: &PackageState) -> Self {
+        let diff = old.diff(new);
+        let mut packages = Vec::new();
+        for (names, action) in [
+            (&diff.install, PackageAction::Install),
+            (&diff.update, PackageAction::Update),
+            (&diff.uninstall, PackageAction::Uninstall),
+        ] {
+            for name in names {
+                let old = old.installed.get(name);
+                let new = new.installed.get(name);
+                packages.push(HistoryPackage {
+                    name: name.clone(),
+                    action,
+                    old_version: old.map(|p| p.version.clone()).unwrap_or_default(),
+                    new_version: new.map(|p| p.version.clone()).unwrap_or_default(),
+                    old_blake3: old.map(|p| p.blake3.clone()).unwrap_or_default(),
+                    new_blake3: new.map(|p| p.blake3.clone()).unwrap_or_default(),
+                    old_state: old.cloned(),
+                });
+            }
+        }
+
+        HistoryEntry {
+            id: 0,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            command: String::new(),
+            status: TransactionStatus::Success,
+            committed: 0,
+            error: None,
+            packages,
+        }
+    }
+}
+
+/// Append-only transaction log in [install_path]/var/lib/pkg/history.toml
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct History {
+    #[serde(rename = "transaction")]
+    pub transactions: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn from_sysroot<P: AsRef<Path>>(install_path: P) -> Result<Self, PackageError> {
+        let history_path = install_path.as_ref().join(crate::PACKAGES_HISTORY_PATH);
+
+        match std::fs::read_to_string(&history_path) {
+            Ok(toml) => {
+                toml::from_str(&toml).map_err(|e| PackageError::Parse(e, Some(history_path)))
+            }
+            Err(_) => Ok(History::default()),
+        }
+    }
+
+    /// Append an entry without rewriting earlier ones. Returns id assigned to the entry.
+    pub fn append_to_sysroot<P: AsRef<Path>>(
+        install_path: P,
+        mut entry: HistoryEntry,
+    ) -> Result<u64, PackageError> {
+        let history = Self::from_sysroot(&install_path)?;
+        entry.id = history.transactions.last().map_or(1, |t| t.id + 1);
+
+        let history_path = install_path.as_ref().join(crate::PACKAGES_HISTORY_PATH);
+        let io_err =
+            |err: std::io::Error| PackageError::FileError(err.raw_os_error(), history_path.clone());
+        std::fs::create_dir_all(history_path.parent().unwrap()).map_err(io_err)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&history_path)
+            .map_err(io_err)?;
+        let toml = toml::to_string(&History {
+            transactions: vec![entry.clone()],
+        })
+        .unwrap();
+        file.write_all(format!("{toml}\n").as_bytes())
+            .map_err(io_err)?;
+
+        Ok(entry.id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.transactions.iter().find(|t| t.id == id)
+    }
+
+    /// last transaction that is successfully applied
+    pub fn last_success(&self) -> Option<&HistoryEntry> {
+        self.transactions
+            .iter()
+            .rev()
+            .find(|t| t.status == TransactionStatus::Success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_history() -> Result<(), PackageError> {
+        let sysroot = std::env::temp_dir().join("pkg_test_append_history");
+        let _ = std::fs::remove_dir_all(&sysroot);
+
+        let old = PackageState::default();
+        let mut new = old.clone();
+        new.installed.insert(
+            PackageName::new("nano").unwrap(),
+            crate::InstallState {
+                blake3: "hash".into(),
+                version: "1.0.0".into(),
+                ..Default::default()
+            },
+        );
+
+        let mut entry = HistoryEntry::new(&old, &new);
+        assert_eq!(entry.packages[0].old_state, None);
+        entry.command = "pkg install nano".into();
+        assert_eq!(History::append_to_sysroot(&sysroot, entry.clone())?, 1);
+        entry.status = TransactionStatus::Aborted;
+        entry.error = Some("interrupted".into());
+        assert_eq!(History::append_to_sysroot(&sysroot, entry)?, 2);
+
+        let history = History::from_sysroot(&sysroot)?;
+        assert_eq!(history.transactions.len(), 2);
+        let first = history.get(1).unwrap();
+        assert_eq!(first.status, TransactionStatus::Success);
+        assert_eq!(first.packages[0].action, PackageAction::Install);
+        assert_eq!(first.packages[0].new_version, "1.0.0");
+        assert_eq!(
+            history.get(2).unwrap().error.as_deref(),
+            Some("interrupted")
+        );
+        assert_eq!(history.last_success().unwrap().id, 1);
+
+        let _ = std::fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+}
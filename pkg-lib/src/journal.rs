@@ -0,0 +1,133 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{PackageAction, PackageError, PackageName, PackageState, RepoPublicKeyFile};
+
+/// A package change in a transaction being committed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JournalItem {
+    pub name: PackageName,
+    pub action: PackageAction,
+    /// archive of the new build, none if uninstalled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<PathBuf>,
+    /// public key of the new build archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<RepoPublicKeyFile>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub old_blake3: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub new_blake3: String,
+}
+
+/// Write-ahead journal of a transaction in [install_path]/var/lib/pkg/journal.toml.
+/// Exists only while the transaction is being committed, so finding it means
+/// the transaction was interrupted.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Journal {
+    pub old_state: PackageState,
+    pub new_state: PackageState,
+    #[serde(rename = "item")]
+    pub items: Vec<JournalItem>,
+}
+
+impl Journal {
+    pub fn from_sysroot<P: AsRef<Path>>(install_path: P) -> Result<Option<Self>, PackageError> {
+        let journal_path = install_path.as_ref().join(crate::PACKAGES_JOURNAL_PATH);
+
+        match std::fs::read_to_string(&journal_path) {
+            Ok(toml) => toml::from_str(&toml)
+                .map(Some)
+                .map_err(|e| PackageError::Parse(e, Some(journal_path))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Write the journal, it's synced to disk before returning
+    pub fn to_sysroot<P: AsRef<Path>>(&self, install_path: P) -> Result<(), std::io::Error> {
+        let journal_path = install_path.as_ref().join(crate::PACKAGES_JOURNAL_PATH);
+        // to_string *should* be safe to unwrap for this struct
+        write_atomic(&journal_path, &toml::to_string(self).unwrap())
+    }
+
+    pub fn remove_from_sysroot<P: AsRef<Path>>(install_path: P) -> Result<(), std::io::Error> {
+        let journal_path = install_path.as_ref().join(crate::PACKAGES_JOURNAL_PATH);
+        match std::fs::remove_file(&journal_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Replace a file so it has either its old or new content if interrupted
+pub(crate) fn write_atomic(path: &Path, contents: &str) -> Result<(), std::io::Error> {
+    let dir = path.parent().unwrap();
+    if !dir.is_dir() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut temp_name = path.file_name().unwrap().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    // make the rename durable, if directories can be opened and synced on this platform
+    match std::fs::File::open(dir).and_then(|dir| dir.sync_all()) {
+        Err(e)
+            if e.kind() != std::io::ErrorKind::Unsupported
+                && e.kind() != std::io::ErrorKind::PermissionDenied =>
+        {
+            Err(e)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_sysroot() -> Result<(), PackageError> {
+        let sysroot = std::env::temp_dir().join("pkg_test_journal_sysroot");
+        let _ = std::fs::remove_dir_all(&sysroot);
+        assert!(Journal::from_sysroot(&sysroot)?.is_none());
+
+        let name = PackageName::new("nano").unwrap();
+        let mut journal = Journal::default();
+        journal.new_state.installed.insert(
+            name.clone(),
+            crate::InstallState {
+                blake3: "hash".into(),
+                ..Default::default()
+            },
+        );
+        journal.items.push(JournalItem {
+            name: name.clone(),
+            action: PackageAction::Install,
+            archive: Some(PathBuf::from("/tmp/nano.pkgar")),
+            pubkey: Some(RepoPublicKeyFile::new([1; 32])),
+            old_blake3: String::new(),
+            new_blake3: "hash".into(),
+        });
+        journal.to_sysroot(&sysroot).unwrap();
+
+        let read = Journal::from_sysroot(&sysroot)?.unwrap();
+        assert_eq!(read.items, journal.items);
+        assert!(read.old_state.installed.is_empty());
+        assert_eq!(read.new_state.installed[&name].blake3, "hash");
+
+        Journal::remove_from_sysroot(&sysroot).unwrap();
+        assert!(Journal::from_sysroot(&sysroot)?.is_none());
+
+        let _ = std::fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+}
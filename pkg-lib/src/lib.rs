@@ -1,14 +1,22 @@
 pub mod backend;
 pub mod callback;
+pub use history::*;
+pub use journal::*;
 #[cfg(feature = "library")]
 pub use library::Library;
+#[cfg(feature = "library")]
+pub use lock::LockMode;
 pub mod net_backend;
 pub use package::*;
 pub use package_state::*;
 pub use repo_manager::*;
 
+mod history;
+mod journal;
 #[cfg(feature = "library")]
 mod library;
+#[cfg(feature = "library")]
+mod lock;
 mod package;
 mod package_state;
 mod repo_manager;
@@ -18,6 +26,15 @@ mod sorensen;
 
 const DOWNLOAD_DIR: &str = "/tmp/pkg_download/";
 const PACKAGES_TOML_PATH: &str = "etc/pkg/packages.toml";
+const PACKAGES_PROTECTED_DIR: &str = "etc/pkg/protected.d";
 const PACKAGES_REMOTE_DIR: &str = "etc/pkg.d";
+const PACKAGES_PREFERENCES_PATH: &str = "etc/pkg/preferences.toml";
+const PACKAGES_CACHE_DIR: &str = "var/cache/pkg";
+const PACKAGES_HISTORY_PATH: &str = "var/lib/pkg/history.toml";
+const PACKAGES_JOURNAL_PATH: &str = "var/lib/pkg/journal.toml";
 #[cfg(feature = "library")]
 const PACKAGES_HEAD_DIR: &str = "var/lib/packages";
+#[cfg(feature = "library")]
+const PACKAGES_LOCK_PATH: &str = "var/lib/pkg/lock";
+#[cfg(feature = "library")]
+const PACKAGES_LOCK_PID_PATH: &str = "var/lib/pkg/lock.pid";
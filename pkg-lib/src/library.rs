@@ -1,20 +1,34 @@
-use std::collections::{btree_map, BTreeMap};
-use std::{cell::RefCell, cmp::Ordering, path::Path, rc::Rc};
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc, time::Duration};
 
-use crate::backend::pkgar_backend::PkgarBackend;
+use crate::backend::pkgar_backend::{PkgarBackend, LOCAL_ARCHIVE_REMOTE};
 use crate::backend::{Backend, Error};
 use crate::net_backend::{DefaultNetBackend, DownloadBackend};
 use crate::repo_manager::RepoManager;
 
 use crate::callback::Callback;
-use crate::package::{PackageInfo, PackageName, RemotePackage};
+use crate::lock::LockMode;
+use crate::package::{
+    Package, PackageFileEntry, PackageInfo, PackageName, RemotePackage, VerifyIssue,
+};
 
-use crate::{sorensen, PackageState};
+use crate::{
+    sorensen, DependencyGraph, DependencyTree, GraphEdge, GraphNode, History, HistoryEntry,
+    HistoryPackage, Journal, PackageAction, PackagePlan, PackageState, RepoPublicKey,
+    TransactionPlan, TransactionStatus,
+};
 
 pub struct Library {
     /// the computed package state before commit
     package_state: PackageState,
     cached_info: BTreeMap<PackageName, RemotePackage>,
+    /// held packages that have updates available
+    held_back: Vec<PackageName>,
+    /// protected packages that are allowed to be uninstalled
+    forced: BTreeSet<PackageName>,
+    /// command line recorded in transaction history
+    command: String,
     backend: Box<dyn Backend>,
     callback: Rc<RefCell<dyn Callback>>,
 }
@@ -25,20 +39,36 @@ impl Library {
         install_path: P,
         target: &str,
         callback: Rc<RefCell<dyn Callback>>,
+    ) -> Result<Self, Error> {
+        Self::new_with_lock(install_path, target, callback, LockMode::Exclusive, None)
+    }
+
+    /// Create standard network-based package library, locking package database with lock_mode.
+    /// A shared lock only allows queries. Waits up to lock_wait if another process holds the lock.
+    pub fn new_with_lock<P: AsRef<Path>>(
+        install_path: P,
+        target: &str,
+        callback: Rc<RefCell<dyn Callback>>,
+        lock_mode: LockMode,
+        lock_wait: Option<Duration>,
     ) -> Result<Self, Error> {
         let install_path = install_path.as_ref();
 
         let download_backend = DefaultNetBackend::new()?;
 
         let mut repo_manager = RepoManager::new(callback.clone(), Box::new(download_backend));
+        repo_manager.set_download_path(install_path.join(crate::PACKAGES_CACHE_DIR));
         repo_manager.update_remotes(target, install_path)?;
 
-        let backend = PkgarBackend::new(install_path, repo_manager)?;
+        let backend = PkgarBackend::new(install_path, repo_manager, lock_mode, lock_wait)?;
 
         Ok(Library {
             package_state: backend.get_package_state(),
             backend: Box::new(backend),
             cached_info: BTreeMap::new(),
+            held_back: Vec::new(),
+            forced: BTreeSet::new(),
+            command: String::new(),
             callback: callback,
         })
     }
@@ -56,6 +86,7 @@ impl Library {
         let download_backend = DefaultNetBackend::new()?;
 
         let mut repo_manager = RepoManager::new(callback.clone(), Box::new(download_backend));
+        repo_manager.set_download_path(install_path.join(crate::PACKAGES_CACHE_DIR));
 
         repo_manager.add_local(
             "local",
@@ -64,12 +95,15 @@ impl Library {
             pubkey_dir.as_ref(),
         )?;
 
-        let backend = PkgarBackend::new(install_path, repo_manager)?;
+        let backend = PkgarBackend::new(install_path, repo_manager, LockMode::Exclusive, None)?;
 
         Ok(Library {
             package_state: backend.get_package_state(),
             backend: Box::new(backend),
             cached_info: BTreeMap::new(),
+            held_back: Vec::new(),
+            forced: BTreeSet::new(),
+            command: String::new(),
             callback: callback,
         })
     }
@@ -86,25 +120,174 @@ impl Library {
         let download_backend = DefaultNetBackend::new()?;
 
         let mut repo_manager = RepoManager::new(callback.clone(), Box::new(download_backend));
+        repo_manager.set_download_path(install_path.join(crate::PACKAGES_CACHE_DIR));
 
         for remote_url in remote_urls {
             repo_manager.add_remote(remote_url.trim(), target)?;
         }
 
-        let backend = PkgarBackend::new(install_path, repo_manager)?;
+        let backend = PkgarBackend::new(install_path, repo_manager, LockMode::Exclusive, None)?;
 
         Ok(Library {
             package_state: backend.get_package_state(),
             backend: Box::new(backend),
             cached_info: BTreeMap::new(),
+            held_back: Vec::new(),
+            forced: BTreeSet::new(),
+            command: String::new(),
             callback: callback,
         })
     }
 
+    /// override directory of downloaded files, default is [install_path]/var/cache/pkg.
+    /// Archives are named by their hash, so the directory can be shared by multiple installations.
+    pub fn set_cache_dir(&mut self, path: PathBuf) {
+        self.backend.set_cache_dir(path);
+    }
+
+    /// remove downloaded files, or only archives not used by installed packages,
+    /// which is refused if the cache directory is overridden as it may be shared.
+    /// [`Library::rollback`] has to download the removed builds again.
+    /// Returns count and total size of removed files.
+    pub fn clean(&mut self, all: bool) -> Result<(usize, u64), Error> {
+        self.backend.clean_cache(all)
+    }
+
     pub fn get_installed_packages(&self) -> Result<Vec<PackageName>, Error> {
         Ok(self.package_state.get_installed_list())
     }
 
+    /// get the computed package state, including changes not applied yet
+    pub fn get_package_state(&self) -> &PackageState {
+        &self.package_state
+    }
+
+    /// list dependency paths from manually installed or protected packages to the package
+    pub fn why(&self, package: &PackageName) -> Result<Vec<Vec<PackageName>>, Error> {
+        if !self.package_state.installed.contains_key(package) {
+            return Err(Error::PackageNotInstalled(package.clone()));
+        }
+        Ok(self.package_state.why(package))
+    }
+
+    /// build tree of package dependencies.
+    /// Remote metadata is used if the package is not installed or `remote` is set.
+    pub fn depends(
+        &mut self,
+        package: &PackageName,
+        remote: bool,
+        max_depth: Option<usize>,
+    ) -> Result<DependencyTree, Error> {
+        let state = &self.package_state;
+        let cached_info = &mut self.cached_info;
+        let backend = &self.backend;
+        DependencyTree::build(package, max_depth, &mut |name| {
+            if let Some(installed) = state.installed.get(name).filter(|_| !remote) {
+                let dependencies = installed.dependencies.iter().cloned().collect();
+                return Ok((installed.storage_size, dependencies));
+            }
+            let info = match cached_info.entry(name.clone()) {
+                btree_map::Entry::Occupied(occupied_entry) => occupied_entry.into_mut(),
+                btree_map::Entry::Vacant(vacant_entry) => {
+                    vacant_entry.insert(backend.get_package_detail(name)?)
+                }
+            };
+            Ok((info.package.storage_size, info.package.depends.clone()))
+        })
+    }
+
+    /// build graph of packages and their dependencies from remote metadata
+    pub fn remote_graph(&mut self, packages: Vec<PackageName>) -> Result<DependencyGraph, Error> {
+        let mut graph = DependencyGraph::default();
+        let mut visited = BTreeSet::new();
+        let mut queue = packages.clone();
+        while let Some(name) = queue.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let info = match self.cached_info.entry(name.clone()) {
+                btree_map::Entry::Occupied(occupied_entry) => occupied_entry.into_mut(),
+                btree_map::Entry::Vacant(vacant_entry) => {
+                    vacant_entry.insert(self.backend.get_package_detail(&name)?)
+                }
+            };
+            graph.nodes.push(GraphNode {
+                manual: packages.contains(&name),
+                protected: self.package_state.is_protected(&name),
+                storage_size: info.package.storage_size,
+                name: name.clone(),
+            });
+            for dependency in &info.package.depends {
+                graph.edges.push(GraphEdge {
+                    from: name.clone(),
+                    to: dependency.clone(),
+                });
+                queue.push(dependency.clone());
+            }
+        }
+        Ok(graph)
+    }
+
+    /// build tree of installed packages depending on the package
+    pub fn rdepends(
+        &self,
+        package: &PackageName,
+        max_depth: Option<usize>,
+    ) -> Result<DependencyTree, Error> {
+        let installed = &self.package_state.installed;
+        DependencyTree::build(package, max_depth, &mut |name| {
+            let Some(state) = installed.get(name) else {
+                return Err(Error::PackageNotInstalled(name.clone()));
+            };
+            Ok((
+                state.storage_size,
+                state.dependents.iter().cloned().collect(),
+            ))
+        })
+    }
+
+    /// list installed packages that are marked manually installed or not
+    pub fn get_marked_packages(&self, manual: bool) -> Result<Vec<PackageName>, Error> {
+        Ok(self.package_state.get_marked_list(manual))
+    }
+
+    /// mark installed packages manually installed or not. Returns list of changed packages.
+    pub fn mark(
+        &mut self,
+        manual: bool,
+        packages: Vec<PackageName>,
+    ) -> Result<Vec<PackageName>, Error> {
+        for package in &packages {
+            if !self.package_state.installed.contains_key(package) {
+                return Err(Error::PackageNotInstalled(package.clone()));
+            }
+        }
+        Ok(self.package_state.mark_as_manual(manual, &packages))
+    }
+
+    /// hold installed packages at their installed build. Returns list of changed packages.
+    pub fn hold(&mut self, packages: Vec<PackageName>) -> Result<Vec<PackageName>, Error> {
+        for package in &packages {
+            if !self.package_state.installed.contains_key(package) {
+                return Err(Error::PackageNotInstalled(package.clone()));
+            }
+        }
+        Ok(self.package_state.mark_as_held(true, &packages))
+    }
+
+    /// allow held packages to be updated again. Returns list of changed packages.
+    pub fn unhold(&mut self, packages: Vec<PackageName>) -> Result<Vec<PackageName>, Error> {
+        for package in &packages {
+            // holds left by a removed package can still be cleared
+            if !self.package_state.installed.contains_key(package)
+                && !self.package_state.held.contains(package)
+            {
+                return Err(Error::PackageNotInstalled(package.clone()));
+            }
+        }
+        Ok(self.package_state.mark_as_held(false, &packages))
+    }
+
     pub fn install(&mut self, packages: Vec<PackageName>) -> Result<(), Error> {
         self.callback.borrow_mut().fetch_start(packages.len());
         self.install_inner(packages.clone(), 100)?;
@@ -113,6 +296,24 @@ impl Library {
         Ok(())
     }
 
+    /// install packages from archive paths, using adjacent TOML metadata if exists.
+    /// Dependencies are resolved from configured remotes.
+    pub fn install_local(
+        &mut self,
+        archives: Vec<PathBuf>,
+        pubkey: Option<RepoPublicKey>,
+    ) -> Result<Vec<PackageName>, Error> {
+        let mut packages = Vec::new();
+        for archive in archives {
+            let package = self.backend.open_local_archive(&archive, pubkey)?;
+            let name = package.package.name.clone();
+            self.cached_info.insert(name.clone(), package);
+            packages.push(name);
+        }
+        self.install(packages.clone())?;
+        Ok(packages)
+    }
+
     fn install_inner(&mut self, packages: Vec<PackageName>, iter: u32) -> Result<(), Error> {
         if iter == 0 {
             return Err(Error::RepoRecursion(packages));
@@ -154,7 +355,51 @@ impl Library {
         Ok(())
     }
 
-    /// if packages is empty then update all installed packages
+    /// uninstall packages including protected ones, which needs to be confirmed on apply
+    pub fn uninstall_force(&mut self, packages: Vec<PackageName>) -> Result<(), Error> {
+        let protected = std::mem::take(&mut self.package_state.protected);
+        let protected_drop_in = std::mem::take(&mut self.package_state.protected_drop_in);
+
+        let r = self.uninstall_inner(packages, 100);
+
+        for name in protected.iter().chain(protected_drop_in.iter()) {
+            if !self.package_state.installed.contains_key(name) {
+                self.forced.insert(name.clone());
+            }
+        }
+        self.package_state.protected = protected;
+        self.package_state.protected_drop_in = protected_drop_in;
+        r
+    }
+
+    /// protect packages from being uninstalled. Returns list of changed packages.
+    pub fn protect(&mut self, packages: Vec<PackageName>) -> Result<Vec<PackageName>, Error> {
+        Ok(self.package_state.mark_as_protected(true, &packages))
+    }
+
+    /// allow packages to be uninstalled. Returns list of changed packages.
+    /// Packages listed in "etc/pkg/protected.d" stay protected.
+    pub fn unprotect(&mut self, packages: Vec<PackageName>) -> Result<Vec<PackageName>, Error> {
+        Ok(self.package_state.mark_as_protected(false, &packages))
+    }
+
+    /// check if package can't be accidentally uninstalled
+    pub fn is_protected(&self, package: &PackageName) -> bool {
+        self.package_state.is_protected(package)
+    }
+
+    /// uninstall automatically installed packages that are no longer needed.
+    /// Returns list of packages planned to be uninstalled.
+    pub fn autoremove(&mut self) -> Result<Vec<PackageName>, Error> {
+        let orphans = self.package_state.get_orphans();
+        if !orphans.is_empty() {
+            self.uninstall_inner(orphans.clone(), 100)?;
+        }
+        Ok(orphans)
+    }
+
+    /// if packages is empty then update all installed packages.
+    /// A package is updated from the remote it's installed from, unless it's pinned to another.
     pub fn update(&mut self, mut packages: Vec<PackageName>) -> Result<(), Error> {
         let repo_list = self.backend.get_repository_detail()?;
         let local_list = self.backend.get_package_state();
@@ -164,16 +409,46 @@ impl Library {
 
         let mut new_packages = Vec::new();
         for package in packages {
-            if let Some(source_hash) = repo_list.packages.get(package.as_str()) {
-                if let Some(local_hash) = local_list.installed.get(package.as_str()) {
-                    if local_hash.blake3 != *source_hash {
-                        new_packages.push(package);
-                    }
+            let Some(installed) = local_list.installed.get(&package) else {
+                continue;
+            };
+            let remote = self
+                .backend
+                .get_pin(&package)
+                .unwrap_or_else(|| installed.remote.clone());
+            // packages installed from an archive path are only updated when pinned
+            if !repo_list.remotes.iter().any(|(name, _)| name == &remote) {
+                if remote != LOCAL_ARCHIVE_REMOTE {
+                    self.callback
+                        .borrow_mut()
+                        .update_remote_missing(&package, &remote);
                 }
+                continue;
+            }
+            let Some(build) = repo_list.get_from(package.as_str(), &remote) else {
+                continue;
+            };
+            if installed.blake3 == build.blake3 {
+                continue;
+            }
+            if local_list.held.contains(&package) {
+                self.held_back.push(package);
+            } else {
+                new_packages.push((package, remote));
             }
         }
 
-        self.install(new_packages)
+        self.callback.borrow_mut().fetch_start(new_packages.len());
+        let mut names = Vec::new();
+        for (package, remote) in new_packages {
+            let info = self.backend.get_package_detail_from(&package, &remote)?;
+            self.cached_info.insert(package.clone(), info);
+            names.push(package);
+        }
+        // install reasons are kept
+        self.install_inner(names, 100)?;
+        self.callback.borrow_mut().fetch_end();
+        Ok(())
     }
 
     pub fn get_all_package_names(&mut self) -> Result<Vec<PackageName>, Error> {
@@ -233,25 +508,197 @@ impl Library {
         Ok(result)
     }
 
+    /// compute pending changes with predicted file conflicts,
+    /// without downloading archives or committing anything
+    pub fn plan(&mut self) -> Result<TransactionPlan, Error> {
+        let old_state = self.backend.get_package_state();
+        let mut list = old_state.diff(&self.package_state);
+        list.held = self.held_back.clone();
+
+        let mut packages = Vec::new();
+        let mut incoming = Vec::new();
+        let changes = list
+            .install
+            .iter()
+            .map(|p| (p, PackageAction::Install))
+            .chain(list.update.iter().map(|p| (p, PackageAction::Update)))
+            .chain(list.uninstall.iter().map(|p| (p, PackageAction::Uninstall)));
+        for (name, action) in changes {
+            let old = old_state.installed.get(name);
+            let new = self.package_state.installed.get(name);
+            let Some(state) = new.or(old) else {
+                continue;
+            };
+            packages.push(PackagePlan {
+                name: name.clone(),
+                action,
+                remote: state.remote.clone(),
+                old_version: old.map(|s| s.version.clone()).unwrap_or_default(),
+                new_version: new.map(|s| s.version.clone()).unwrap_or_default(),
+                network_size: new.map(|s| s.network_size).unwrap_or_default(),
+                storage_size: state.storage_size,
+            });
+            if action != PackageAction::Uninstall {
+                if let Some(cache) = self.cached_info.get(name) {
+                    incoming.push(cache.clone());
+                }
+            }
+        }
+
+        let (conflicts, unchecked) = self.backend.predict_conflicts(&list.uninstall, &incoming)?;
+
+        Ok(TransactionPlan {
+            list,
+            packages,
+            conflicts,
+            unchecked,
+        })
+    }
+
     pub fn abort(&mut self) -> Result<usize, Error> {
         self.backend.abort_state()
     }
 
     pub fn apply(&mut self) -> Result<usize, Error> {
-        self.apply_inner()
+        let entry = HistoryEntry::new(&self.backend.get_package_state(), &self.package_state);
+        self.transaction(entry, Self::apply_inner)
+    }
+
+    // runs a transaction unless another one was interrupted, and records it in history
+    fn transaction(
+        &mut self,
+        mut entry: HistoryEntry,
+        run: impl FnOnce(&mut Self) -> Result<usize, Error>,
+    ) -> Result<usize, Error> {
+        if self.backend.get_interrupted().is_some() {
+            return Err(Error::TransactionInterrupted);
+        }
+        let result = run(self);
+        if entry.packages.is_empty() {
+            return result;
+        }
+
+        entry.command = self.command.clone();
+        match &result {
+            Ok(committed) => entry.committed = *committed,
+            Err(e) => {
+                entry.status = TransactionStatus::Aborted;
+                entry.error = Some(e.to_string());
+            }
+        }
+        self.backend.append_history(entry)?;
+        result
+    }
+
+    /// plan to revert a transaction, reinstall removed packages and restore updated packages
+    /// to the builds recorded in history. Latest successful transaction if id is none.
+    pub fn rollback(&mut self, id: Option<u64>) -> Result<u64, Error> {
+        let history = self.backend.get_history()?;
+        let entry = match id {
+            Some(id) => history.get(id).ok_or(Error::TransactionNotFound(id))?,
+            None => history
+                .last_success()
+                .ok_or(Error::TransactionNotFound(0))?,
+        };
+        if entry.status != TransactionStatus::Success {
+            return Err(Error::TransactionNotApplied(entry.id));
+        }
+
+        let mut restore = Vec::new();
+        for p in &entry.packages {
+            if p.action == PackageAction::Install {
+                restore.push((p.name.clone(), None));
+                continue;
+            }
+            let Some(state) = p.old_state.clone() else {
+                return Err(Error::RollbackUnavailable(p.name.clone()));
+            };
+            // the exact build is fetched by its hash
+            let package = Package {
+                name: p.name.clone(),
+                version: state.version.clone(),
+                blake3: state.blake3.clone(),
+                storage_size: state.storage_size,
+                network_size: state.network_size,
+                depends: state.dependencies.iter().cloned().collect(),
+                ..Default::default()
+            };
+            self.cached_info.insert(
+                p.name.clone(),
+                RemotePackage {
+                    package,
+                    remote: state.remote.clone(),
+                },
+            );
+            restore.push((p.name.clone(), Some(state)));
+        }
+        self.package_state.restore(restore)?;
+        Ok(entry.id)
+    }
+
+    /// journal of a transaction interrupted before it was fully committed, e.g. by power loss
+    pub fn get_interrupted(&self) -> Option<&Journal> {
+        self.backend.get_interrupted()
+    }
+
+    /// finish the interrupted transaction, or revert it if `rollback`. Recorded in history.
+    pub fn resume(&mut self, rollback: bool) -> Result<usize, Error> {
+        let journal = self
+            .backend
+            .get_interrupted()
+            .ok_or(Error::NoInterruptedTransaction)?;
+        let mut entry = HistoryEntry::new(&journal.old_state, &journal.new_state);
+        entry.command = self.command.clone();
+
+        let committed = self.backend.resume(!rollback)?;
+        self.package_state = self.backend.get_package_state();
+        entry.committed = committed;
+        if rollback {
+            entry.status = TransactionStatus::Aborted;
+            entry.error = Some("interrupted, rolled back".into());
+        }
+        self.backend.append_history(entry)?;
+        Ok(committed)
+    }
+
+    /// set command line to be recorded in transaction history
+    pub fn set_history_command(&mut self, command: impl Into<String>) {
+        self.command = command.into();
+    }
+
+    /// read transaction history of this installation
+    pub fn get_history(&self) -> Result<History, Error> {
+        self.backend.get_history()
     }
 
     fn apply_inner(&mut self) -> Result<usize, Error> {
-        let diff = self.backend.get_package_state().diff(&self.package_state);
+        let mut diff = self.backend.get_package_state().diff(&self.package_state);
+        diff.held = std::mem::take(&mut self.held_back);
         if diff.is_empty() {
+            // there might be changes that doesn't involve files, e.g. install reasons
+            self.backend.set_package_state(self.package_state.clone())?;
             return Ok(0);
         }
 
         self.callback.borrow_mut().install_prompt(&diff)?;
 
+        let forced: Vec<PackageName> = diff
+            .uninstall
+            .iter()
+            .filter(|p| self.forced.contains(*p))
+            .cloned()
+            .collect();
+        if !forced.is_empty() {
+            self.callback
+                .borrow_mut()
+                .uninstall_protected_prompt(&forced)?;
+        }
+
         for package in &diff.uninstall {
             // TODO: Allow self-trusting the package?
-            let r = self.backend.uninstall(package.clone());
+            let r = self
+                .backend
+                .uninstall(package.clone(), self.forced.contains(package));
             if let Err(Error::RepoCacheNotFound(e)) = &r {
                 eprintln!("Repository source of {e} is not valid, please reinstall repository public keys to allow erasing, or reinstall the package.");
             }
@@ -282,6 +729,133 @@ impl Library {
         self.backend.commit_state(self.package_state.clone())
     }
 
+    /// download and verify package archives into dest directory without installing them.
+    /// Returns list of downloaded packages.
+    pub fn download(
+        &mut self,
+        packages: Vec<PackageName>,
+        resolve_deps: bool,
+        dest: &Path,
+    ) -> Result<Vec<PackageName>, Error> {
+        let mut downloaded = Vec::new();
+        let mut queue: Vec<PackageName> = packages.into_iter().rev().collect();
+        while let Some(name) = queue.pop() {
+            if downloaded.contains(&name) {
+                continue;
+            }
+            let info = match self.cached_info.entry(name.clone()) {
+                btree_map::Entry::Occupied(occupied_entry) => occupied_entry.into_mut(),
+                btree_map::Entry::Vacant(vacant_entry) => {
+                    vacant_entry.insert(self.backend.get_package_detail(&name)?)
+                }
+            };
+            self.backend.download(info, dest)?;
+            if resolve_deps {
+                queue.extend(info.package.depends.iter().rev().cloned());
+            }
+            downloaded.push(name);
+        }
+        Ok(downloaded)
+    }
+
+    /// list files owned by an installed package
+    pub fn get_package_files(&self, package: &PackageName) -> Result<Vec<PackageFileEntry>, Error> {
+        if !self.package_state.installed.contains_key(package) {
+            return Err(Error::PackageNotInstalled(package.clone()));
+        }
+        self.backend.get_package_files(package)
+    }
+
+    /// check installed files against their package heads, all installed packages if empty
+    pub fn verify(&self, packages: Vec<PackageName>) -> Result<Vec<VerifyIssue>, Error> {
+        let installed = self.backend.get_package_state().installed;
+        for package in &packages {
+            if !installed.contains_key(package) {
+                return Err(Error::PackageNotInstalled(package.clone()));
+            }
+        }
+        let packages = if packages.is_empty() {
+            installed.into_keys().collect()
+        } else {
+            packages
+        };
+        self.backend.verify_packages(&packages)
+    }
+
+    /// re-extract all files of installed packages from a newly downloaded pkgar of the same build.
+    /// Nothing is committed if any of them fails.
+    pub fn repair(&mut self, packages: Vec<PackageName>) -> Result<usize, Error> {
+        let state = self.backend.get_package_state();
+        // recorded as updates to the same build
+        let mut entry = HistoryEntry::new(&state, &state);
+        for name in &packages {
+            if let Some(installed) = state.installed.get(name) {
+                entry.packages.push(HistoryPackage {
+                    name: name.clone(),
+                    action: PackageAction::Update,
+                    old_version: installed.version.clone(),
+                    new_version: installed.version.clone(),
+                    old_blake3: installed.blake3.clone(),
+                    new_blake3: installed.blake3.clone(),
+                    old_state: Some(installed.clone()),
+                });
+            }
+        }
+
+        self.transaction(entry, |library| {
+            if let Err(err) = library.stage_repair(&state, &packages) {
+                library.backend.abort_state()?;
+                return Err(err);
+            }
+            library.backend.commit_state(state.clone())
+        })
+    }
+
+    fn stage_repair(
+        &mut self,
+        state: &PackageState,
+        packages: &[PackageName],
+    ) -> Result<(), Error> {
+        for package in packages {
+            let Some(installed) = state.installed.get(package) else {
+                return Err(Error::PackageNotInstalled(package.clone()));
+            };
+            let remote = self
+                .backend
+                .get_package_detail_from(package, &installed.remote)?;
+            if remote.package.blake3 != installed.blake3 {
+                return Err(Error::PackageBuildUnavailable(package.clone()));
+            }
+            self.backend.install(remote)?;
+        }
+
+        self.callback
+            .borrow_mut()
+            .install_check_conflict(self.backend.commit_check_conflict()?)
+    }
+
+    /// index installed file paths, relative to install root, to packages owning them
+    pub fn get_file_owners(&self) -> Result<BTreeMap<PathBuf, Vec<PackageName>>, Error> {
+        let mut owners: BTreeMap<PathBuf, Vec<PackageName>> = BTreeMap::new();
+        for package in self.backend.get_package_state().installed.keys() {
+            for file in self.backend.get_package_files(package)? {
+                owners.entry(file.path).or_default().push(package.clone());
+            }
+        }
+        Ok(owners)
+    }
+
+    /// find installed packages owning a path relative to install root.
+    /// Returns empty list if not owned by any package, or multiple packages if conflicting.
+    pub fn owner_of<P: AsRef<Path>>(&self, path: P) -> Result<Vec<PackageName>, Error> {
+        let path: PathBuf = path
+            .as_ref()
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        Ok(self.get_file_owners()?.remove(&path).unwrap_or_default())
+    }
+
     pub fn info(&mut self, package: PackageName) -> Result<PackageInfo, Error> {
         let installed = self.package_state.get_installed_list().contains(&package);
         let package = self.backend.get_package_detail(&package)?;
@@ -289,3 +863,482 @@ impl Library {
         Ok(PackageInfo { installed, package })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        callback::SilentCallback, FileConflict, InstallState, MergedRepository, PackageFileEntry,
+        RemoteName, Repository,
+    };
+
+    /// backend with repositories in memory, recording what's applied
+    #[derive(Default)]
+    struct MockBackend {
+        state: PackageState,
+        remotes: Vec<(RemoteName, Repository)>,
+        pins: BTreeMap<PackageName, RemoteName>,
+        /// packages which install fails
+        broken: BTreeSet<PackageName>,
+        files: BTreeMap<PackageName, Vec<PackageFileEntry>>,
+        installed: Rc<RefCell<Vec<RemotePackage>>>,
+        committed: Rc<RefCell<Option<PackageState>>>,
+        conflicts: Vec<pkgar::TransactionConflict>,
+        history: Rc<RefCell<Vec<HistoryEntry>>>,
+        interrupted: Option<Journal>,
+    }
+
+    impl MockBackend {
+        fn install_state(&mut self, name: &str, remote: &str, blake3: &str) {
+            self.state.installed.insert(
+                cpkg(name),
+                InstallState {
+                    remote: remote.into(),
+                    blake3: blake3.into(),
+                    version: "1".into(),
+                    manual: true,
+                    ..Default::default()
+                },
+            );
+        }
+
+        fn publish(&mut self, remote: &str, name: &str, blake3: &str) {
+            if !self.remotes.iter().any(|(r, _)| r == remote) {
+                self.remotes.push((remote.into(), Repository::default()));
+            }
+            let (_, repository) = self.remotes.iter_mut().find(|(r, _)| r == remote).unwrap();
+            repository.packages.insert(name.into(), blake3.into());
+        }
+    }
+
+    impl Backend for MockBackend {
+        fn install(&mut self, package: RemotePackage) -> Result<(), Error> {
+            if self.broken.contains(&package.package.name) {
+                return Err(Error::PackageNotFound(package.package.name));
+            }
+            self.installed.borrow_mut().push(package);
+            Ok(())
+        }
+        fn open_local_archive(
+            &mut self,
+            path: &Path,
+            _: Option<RepoPublicKey>,
+        ) -> Result<RemotePackage, Error> {
+            Err(Error::ArchiveNotTrusted(path.to_string_lossy().into()))
+        }
+        fn uninstall(&mut self, _: PackageName, _: bool) -> Result<(), Error> {
+            Ok(())
+        }
+        fn upgrade(&mut self, package: &RemotePackage) -> Result<(), Error> {
+            self.install(package.clone())
+        }
+        fn download(&mut self, _: &RemotePackage, _: &Path) -> Result<(), Error> {
+            Ok(())
+        }
+        fn set_cache_dir(&mut self, _: PathBuf) {}
+        fn clean_cache(&self, _: bool) -> Result<(usize, u64), Error> {
+            Ok((0, 0))
+        }
+        fn get_package_detail(&self, package: &PackageName) -> Result<RemotePackage, Error> {
+            let repository = self.get_repository_detail()?;
+            let build = repository
+                .get(package.as_str())
+                .ok_or_else(|| Error::PackageNotFound(package.clone()))?;
+            self.get_package_detail_from(package, &build.remote)
+        }
+        fn get_package_detail_from(
+            &self,
+            package: &PackageName,
+            remote: &RemoteName,
+        ) -> Result<RemotePackage, Error> {
+            let repository = self.get_repository_detail()?;
+            let build = repository
+                .get_from(package.as_str(), remote)
+                .ok_or_else(|| Error::PackageNotFound(package.clone()))?;
+            Ok(RemotePackage {
+                package: Package {
+                    name: package.clone(),
+                    version: "2".into(),
+                    blake3: build.blake3.clone(),
+                    ..Default::default()
+                },
+                remote: remote.clone(),
+            })
+        }
+        fn get_pin(&self, package: &PackageName) -> Option<RemoteName> {
+            self.pins.get(package).cloned()
+        }
+        fn get_repository_detail(&self) -> Result<MergedRepository, Error> {
+            Ok(MergedRepository::new(self.remotes.clone()))
+        }
+        fn get_package_state(&self) -> PackageState {
+            self.state.clone()
+        }
+        fn get_package_files(&self, package: &PackageName) -> Result<Vec<PackageFileEntry>, Error> {
+            Ok(self.files.get(package).cloned().unwrap_or_default())
+        }
+        fn verify_packages(&self, _: &[PackageName]) -> Result<Vec<VerifyIssue>, Error> {
+            Ok(Vec::new())
+        }
+        fn predict_conflicts(
+            &self,
+            _: &[PackageName],
+            _: &[RemotePackage],
+        ) -> Result<(Vec<FileConflict>, Vec<PackageName>), Error> {
+            Ok((Vec::new(), Vec::new()))
+        }
+        fn commit_check_conflict(&self) -> Result<&Vec<pkgar::TransactionConflict>, Error> {
+            Ok(&self.conflicts)
+        }
+        fn commit_state(&mut self, new_state: PackageState) -> Result<usize, Error> {
+            *self.committed.borrow_mut() = Some(new_state.clone());
+            self.state = new_state;
+            Ok(self.installed.borrow().len())
+        }
+        fn set_package_state(&mut self, new_state: PackageState) -> Result<(), Error> {
+            self.state = new_state;
+            Ok(())
+        }
+        fn get_history(&self) -> Result<History, Error> {
+            Ok(History::default())
+        }
+        fn append_history(&mut self, entry: HistoryEntry) -> Result<u64, Error> {
+            self.history.borrow_mut().push(entry);
+            Ok(self.history.borrow().len() as u64)
+        }
+        fn get_interrupted(&self) -> Option<&Journal> {
+            self.interrupted.as_ref()
+        }
+        fn resume(&mut self, _: bool) -> Result<usize, Error> {
+            Err(Error::NoInterruptedTransaction)
+        }
+        fn abort_state(&mut self) -> Result<usize, Error> {
+            self.installed.borrow_mut().clear();
+            Ok(0)
+        }
+    }
+
+    fn cpkg(name: &str) -> PackageName {
+        PackageName::new(name).unwrap()
+    }
+
+    fn mock_files(paths: &[&str]) -> Vec<PackageFileEntry> {
+        paths
+            .iter()
+            .map(|path| PackageFileEntry {
+                path: PathBuf::from(path),
+                mode: 0o100644,
+                size: 0,
+                blake3: String::new(),
+            })
+            .collect()
+    }
+
+    fn mock_library(backend: MockBackend) -> Library {
+        Library {
+            package_state: backend.get_package_state(),
+            backend: Box::new(backend),
+            cached_info: BTreeMap::new(),
+            held_back: Vec::new(),
+            forced: BTreeSet::new(),
+            command: String::new(),
+            callback: Rc::new(RefCell::new(SilentCallback::new())),
+        }
+    }
+
+    #[test]
+    fn test_update_from_installed_remote() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "b", "nano-old");
+        backend.install_state("vim", "b", "vim-old");
+        backend.install_state("gcc", "b", "gcc-old");
+        backend.install_state("curl", "b", "curl-b");
+        backend.publish("a", "nano", "nano-a");
+        backend.publish("b", "nano", "nano-b");
+        backend.publish("a", "vim", "vim-a");
+        backend.publish("a", "gcc", "gcc-a");
+        backend.publish("b", "gcc", "gcc-b");
+        backend.publish("a", "curl", "curl-a");
+        backend.publish("b", "curl", "curl-b");
+        backend.pins.insert(cpkg("gcc"), "a".into());
+
+        let mut library = mock_library(backend);
+        library.update(Vec::new())?;
+        let state = library.get_package_state();
+        let build = |name| {
+            let state = &state.installed[&cpkg(name)];
+            (state.remote.as_str(), state.blake3.as_str())
+        };
+        // a higher priority remote offering the package doesn't take it over
+        assert_eq!(build("nano"), ("b", "nano-b"));
+        assert_eq!(build("curl"), ("b", "curl-b"));
+        // nor any remote if its own doesn't offer it anymore
+        assert_eq!(build("vim"), ("b", "vim-old"));
+        // unless it's pinned
+        assert_eq!(build("gcc"), ("a", "gcc-a"));
+        assert!(state.installed[&cpkg("nano")].manual);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_skips_local_archives() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "local", "nano-local");
+        backend.install_state("vim", "local", "vim-local");
+        backend.publish("a", "nano", "nano-a");
+        backend.publish("a", "vim", "vim-a");
+        backend.pins.insert(cpkg("vim"), "a".into());
+
+        let mut library = mock_library(backend);
+        library.update(Vec::new())?;
+        let state = library.get_package_state();
+        assert_eq!(state.installed[&cpkg("nano")].remote, "local");
+        assert_eq!(state.installed[&cpkg("nano")].blake3, "nano-local");
+        assert_eq!(state.installed[&cpkg("vim")].remote, "a");
+        assert_eq!(state.installed[&cpkg("vim")].blake3, "vim-a");
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RemoteMissingCallback {
+        missing: Vec<(PackageName, String)>,
+    }
+
+    impl Callback for RemoteMissingCallback {
+        fn fetch_start(&mut self, _: usize) {}
+        fn fetch_package_name(&mut self, _: &PackageName) {}
+        fn fetch_package_increment(&mut self, _: usize, _: usize) {}
+        fn fetch_end(&mut self) {}
+        fn install_prompt(&mut self, _: &crate::PackageList) -> Result<(), Error> {
+            Ok(())
+        }
+        fn uninstall_protected_prompt(&mut self, _: &[PackageName]) -> Result<(), Error> {
+            Ok(())
+        }
+        fn install_check_conflict(
+            &mut self,
+            _: &Vec<pkgar::TransactionConflict>,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+        fn install_extract(&mut self, _: &RemotePackage) {}
+        fn lock_wait(&mut self, _: &str) {}
+        fn protected_ignored(&mut self, _: &Path, _: &str) {}
+        fn update_remote_missing(&mut self, pkg_name: &PackageName, remote: &str) {
+            self.missing.push((pkg_name.clone(), remote.into()));
+        }
+        fn download_start(&mut self, _: u64, _: &str) {}
+        fn download_increment(&mut self, _: u64) {}
+        fn download_end(&mut self) {}
+        fn commit_start(&mut self, _: usize) {}
+        fn commit_increment(&mut self, _: &pkgar::Transaction) {}
+        fn commit_end(&mut self) {}
+        fn abort_start(&mut self, _: usize) {}
+        fn abort_increment(&mut self, _: &pkgar::Transaction) {}
+        fn abort_end(&mut self) {}
+    }
+
+    #[test]
+    fn test_update_reports_missing_remote() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "removed", "nano-old");
+        backend.install_state("vim", "local", "vim-local");
+        backend.install_state("gcc", "a", "gcc-old");
+        backend.publish("a", "nano", "nano-a");
+        backend.publish("a", "vim", "vim-a");
+        backend.publish("a", "gcc", "gcc-a");
+
+        let callback = Rc::new(RefCell::new(RemoteMissingCallback::default()));
+        let mut library = mock_library(backend);
+        library.callback = callback.clone();
+        library.update(Vec::new())?;
+        assert_eq!(
+            callback.borrow().missing,
+            vec![(cpkg("nano"), "removed".to_string())]
+        );
+        let state = library.get_package_state();
+        assert_eq!(state.installed[&cpkg("nano")].blake3, "nano-old");
+        assert_eq!(state.installed[&cpkg("gcc")].blake3, "gcc-a");
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_aborts_on_error() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.install_state("vim", "a", "vim-a");
+        backend.install_state("gcc", "a", "gcc-old");
+        backend.publish("a", "nano", "nano-a");
+        backend.publish("a", "vim", "vim-a");
+        backend.publish("a", "gcc", "gcc-a");
+        backend.broken.insert(cpkg("vim"));
+        let installed = backend.installed.clone();
+        let committed = backend.committed.clone();
+
+        let mut library = mock_library(backend);
+        let err = library.repair(vec![cpkg("nano"), cpkg("vim")]).unwrap_err();
+        assert!(matches!(err, Error::PackageNotFound(p) if p == cpkg("vim")));
+        // staged repair of nano is dropped
+        assert!(installed.borrow().is_empty());
+        assert!(committed.borrow().is_none());
+
+        let err = library.repair(vec![cpkg("nano"), cpkg("gcc")]).unwrap_err();
+        assert!(matches!(err, Error::PackageBuildUnavailable(p) if p == cpkg("gcc")));
+        assert!(installed.borrow().is_empty());
+        assert!(committed.borrow().is_none());
+
+        assert_eq!(library.repair(vec![cpkg("nano")])?, 1);
+        assert!(committed.borrow().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_history() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.install_state("vim", "a", "vim-a");
+        backend.publish("a", "nano", "nano-a");
+        backend.publish("a", "vim", "vim-a");
+        backend.broken.insert(cpkg("vim"));
+        let history = backend.history.clone();
+
+        let mut library = mock_library(backend);
+        assert_eq!(library.repair(vec![cpkg("nano")])?, 1);
+        assert!(library.repair(vec![cpkg("vim")]).is_err());
+        let history = history.borrow();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].status, TransactionStatus::Success);
+        assert_eq!(history[0].committed, 1);
+        let package = &history[0].packages[0];
+        assert_eq!(package.name, cpkg("nano"));
+        assert_eq!(package.action, PackageAction::Update);
+        assert_eq!(package.old_blake3, "nano-a");
+        assert_eq!(package.new_blake3, "nano-a");
+        assert_eq!(history[1].status, TransactionStatus::Aborted);
+        assert_eq!(history[1].packages[0].name, cpkg("vim"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_interrupted() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.publish("a", "nano", "nano-a");
+        backend.interrupted = Some(Journal::default());
+        let installed = backend.installed.clone();
+        let history = backend.history.clone();
+
+        let mut library = mock_library(backend);
+        assert!(matches!(
+            library.repair(vec![cpkg("nano")]),
+            Err(Error::TransactionInterrupted)
+        ));
+        assert!(installed.borrow().is_empty());
+        assert!(history.borrow().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_from_installed_remote() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("gcc", "b", "gcc-b");
+        backend.publish("a", "gcc", "gcc-a");
+        backend.publish("b", "gcc", "gcc-b");
+        let installed = backend.installed.clone();
+
+        let mut library = mock_library(backend);
+        assert_eq!(library.repair(vec![cpkg("gcc")])?, 1);
+        assert_eq!(installed.borrow()[0].remote, "b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hold() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.state.held.insert(cpkg("removed"));
+
+        let mut library = mock_library(backend);
+        assert!(matches!(
+            library.hold(vec![cpkg("vim")]),
+            Err(Error::PackageNotInstalled(_))
+        ));
+        assert!(matches!(
+            library.unhold(vec![cpkg("vim")]),
+            Err(Error::PackageNotInstalled(_))
+        ));
+        assert_eq!(library.hold(vec![cpkg("nano")])?, vec![cpkg("nano")]);
+        assert_eq!(
+            library.unhold(vec![cpkg("nano"), cpkg("removed")])?,
+            vec![cpkg("nano"), cpkg("removed")]
+        );
+        assert!(library.package_state.held.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.install_state("ncurses", "a", "ncurses-a");
+
+        let mut library = mock_library(backend);
+        assert!(matches!(
+            library.mark(false, vec![cpkg("nano"), cpkg("vim")]),
+            Err(Error::PackageNotInstalled(p)) if p == cpkg("vim")
+        ));
+        assert_eq!(library.get_marked_packages(false)?, vec![]);
+        assert_eq!(
+            library.mark(false, vec![cpkg("ncurses")])?,
+            vec![cpkg("ncurses")]
+        );
+        assert_eq!(library.mark(false, vec![cpkg("ncurses")])?, vec![]);
+        assert_eq!(library.get_marked_packages(true)?, vec![cpkg("nano")]);
+        assert_eq!(library.get_marked_packages(false)?, vec![cpkg("ncurses")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_files() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.install_state("meta", "a", "");
+        let files = mock_files(&["usr/bin/nano", "usr/share/man/man1/nano.1"]);
+        backend.files.insert(cpkg("nano"), files.clone());
+
+        let library = mock_library(backend);
+        assert_eq!(library.get_package_files(&cpkg("nano"))?, files);
+        assert_eq!(library.get_package_files(&cpkg("meta"))?, vec![]);
+        assert!(matches!(
+            library.get_package_files(&cpkg("vim")),
+            Err(Error::PackageNotInstalled(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_owner_of() -> Result<(), Error> {
+        let mut backend = MockBackend::default();
+        backend.install_state("nano", "a", "nano-a");
+        backend.install_state("vim", "a", "vim-a");
+        backend.files.insert(
+            cpkg("nano"),
+            mock_files(&["usr/bin/nano", "usr/share/editor"]),
+        );
+        backend.files.insert(
+            cpkg("vim"),
+            mock_files(&["usr/bin/vim", "usr/share/editor"]),
+        );
+
+        let library = mock_library(backend);
+        for path in ["/usr/bin/nano", "usr/bin/nano", "./usr/bin//nano"] {
+            assert_eq!(library.owner_of(path)?, vec![cpkg("nano")], "{path}");
+        }
+        assert_eq!(
+            library.owner_of("/usr/share/editor")?,
+            vec![cpkg("nano"), cpkg("vim")]
+        );
+        assert_eq!(library.owner_of("/usr/bin/ed")?, vec![]);
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions, TryLockError},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{backend::Error, callback::Callback};
+
+/// interval between attempts while waiting for a lock
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the package database of an installation is locked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// read-only queries, shared with other readers
+    Shared,
+    /// changing the installation, no one else may hold the lock
+    Exclusive,
+}
+
+/// Advisory lock of [install_path]/var/lib/pkg/lock, released when dropped.
+/// An exclusive holder writes its process id and command line into the file.
+///
+/// Where file locks are not supported, e.g. on Redox, an exclusive holder creates
+/// [install_path]/var/lib/pkg/lock.pid instead and readers wait until it's removed.
+/// Such a file left by a process that no longer exists must be removed by hand.
+pub(crate) struct PackageLock {
+    /// none if the lock file can't be created by a reader, then no writer could have held it
+    file: Option<File>,
+    /// lock.pid created by this exclusive holder
+    pid_path: Option<PathBuf>,
+    mode: LockMode,
+}
+
+impl PackageLock {
+    /// lock the database, waiting up to `wait` if it's held by another process
+    pub(crate) fn acquire(
+        install_path: &Path,
+        mode: LockMode,
+        wait: Option<Duration>,
+        callback: &Rc<RefCell<dyn Callback>>,
+    ) -> Result<Self, Error> {
+        let lock_path = install_path.join(crate::PACKAGES_LOCK_PATH);
+        if mode == LockMode::Exclusive {
+            std::fs::create_dir_all(lock_path.parent().unwrap())?;
+        }
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            // readers may not have write permission
+            Err(_) if mode == LockMode::Shared => match File::open(&lock_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Self::acquire_pid_file(install_path, mode, wait, callback);
+                }
+                Err(e) => return Err(e.into()),
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        let locked = Self::wait_for(wait, callback, || {
+            let result = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock(),
+            };
+            match result {
+                Ok(()) => Ok(None),
+                Err(TryLockError::WouldBlock) => Ok(Some(Self::holder(&file))),
+                Err(TryLockError::Error(e)) => Err(e),
+            }
+        });
+        match locked {
+            Ok(()) => {}
+            Err(Error::IO(e)) if e.kind() == io::ErrorKind::Unsupported => {
+                return Self::acquire_pid_file(install_path, mode, wait, callback);
+            }
+            Err(e) => return Err(e),
+        }
+
+        let mut lock = PackageLock {
+            file: Some(file),
+            pid_path: None,
+            mode,
+        };
+        if mode == LockMode::Exclusive {
+            lock.write_holder(&Self::holder_content())?;
+        }
+        Ok(lock)
+    }
+
+    // locks by creating lock.pid, for platforms without file locks.
+    // A writer can't see readers, it only keeps out other writers and new readers.
+    fn acquire_pid_file(
+        install_path: &Path,
+        mode: LockMode,
+        wait: Option<Duration>,
+        callback: &Rc<RefCell<dyn Callback>>,
+    ) -> Result<Self, Error> {
+        let pid_path = install_path.join(crate::PACKAGES_LOCK_PID_PATH);
+        let pid_holder = || match File::open(&pid_path) {
+            Ok(file) => Some(Self::holder(&file)),
+            Err(_) => None,
+        };
+        Self::wait_for(wait, callback, || {
+            if mode == LockMode::Shared {
+                return Ok(pid_holder());
+            }
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&pid_path)
+            {
+                Ok(mut file) => {
+                    let written = file
+                        .write_all(Self::holder_content().as_bytes())
+                        .and_then(|_| file.sync_all());
+                    if written.is_err() {
+                        let _ = std::fs::remove_file(&pid_path);
+                    }
+                    written.map(|_| None)
+                }
+                // held by another writer, or removed in between and tried again
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    Ok(Some(pid_holder().unwrap_or_else(|| Self::holder_of(""))))
+                }
+                Err(e) => Err(e),
+            }
+        })?;
+
+        Ok(PackageLock {
+            file: None,
+            pid_path: (mode == LockMode::Exclusive).then_some(pid_path),
+            mode,
+        })
+    }
+
+    // polls try_lock until it succeeds, which returns the holder while it's locked
+    fn wait_for<F>(
+        wait: Option<Duration>,
+        callback: &Rc<RefCell<dyn Callback>>,
+        mut try_lock: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut() -> Result<Option<String>, io::Error>,
+    {
+        let deadline = wait.map(|wait| Instant::now() + wait);
+        let mut waiting = false;
+        loop {
+            let Some(holder) = try_lock()? else {
+                return Ok(());
+            };
+            if deadline.is_none_or(|deadline| Instant::now() >= deadline) {
+                return Err(Error::PackageLocked(holder));
+            }
+            if !waiting {
+                callback.borrow_mut().lock_wait(&holder);
+                waiting = true;
+            }
+            thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+
+    pub(crate) fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    // process id and command line of this process
+    fn holder_content() -> String {
+        let command = std::env::args().collect::<Vec<_>>().join(" ");
+        format!("{}\n{}\n", std::process::id(), command)
+    }
+
+    // describes the process holding the lock from its content
+    fn holder(mut file: &File) -> String {
+        let mut content = String::new();
+        let _ = file
+            .rewind()
+            .and_then(|_| file.read_to_string(&mut content));
+        Self::holder_of(&content)
+    }
+
+    fn holder_of(content: &str) -> String {
+        let mut lines = content.lines();
+        match (lines.next(), lines.next()) {
+            (Some(pid), Some(command)) if !command.is_empty() => {
+                format!("process {pid} ({command})")
+            }
+            (Some(pid), _) if !pid.is_empty() => format!("process {pid}"),
+            // readers don't write their process id
+            _ => "another process".to_string(),
+        }
+    }
+
+    fn write_holder(&mut self, content: &str) -> Result<(), io::Error> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()
+    }
+}
+
+impl Drop for PackageLock {
+    fn drop(&mut self) {
+        if let Some(pid_path) = &self.pid_path {
+            let _ = std::fs::remove_file(pid_path);
+        } else if self.mode == LockMode::Exclusive {
+            let _ = self.write_holder("");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::SilentCallback;
+
+    #[test]
+    fn test_package_lock() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_package_lock");
+        let _ = std::fs::remove_dir_all(&sysroot);
+        let callback: Rc<RefCell<dyn Callback>> = Rc::new(RefCell::new(SilentCallback::new()));
+
+        // no lock file yet
+        let reader = PackageLock::acquire(&sysroot, LockMode::Shared, None, &callback)?;
+        drop(reader);
+
+        let writer = PackageLock::acquire(&sysroot, LockMode::Exclusive, None, &callback)?;
+        let pid = std::process::id().to_string();
+        for mode in [LockMode::Shared, LockMode::Exclusive] {
+            let wait = Some(Duration::from_millis(200));
+            match PackageLock::acquire(&sysroot, mode, wait, &callback) {
+                Err(Error::PackageLocked(holder)) => assert!(holder.contains(&pid)),
+                _ => panic!("lock is not exclusive"),
+            }
+        }
+        drop(writer);
+
+        let reader = PackageLock::acquire(&sysroot, LockMode::Shared, None, &callback)?;
+        let _reader2 = PackageLock::acquire(&sysroot, LockMode::Shared, None, &callback)?;
+        match PackageLock::acquire(&sysroot, LockMode::Exclusive, None, &callback) {
+            Err(Error::PackageLocked(holder)) => assert_eq!(holder, "another process"),
+            _ => panic!("lock is not shared"),
+        }
+        drop(reader);
+
+        let _ = std::fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_lock_pid_file() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_package_lock_pid_file");
+        let _ = std::fs::remove_dir_all(&sysroot);
+        std::fs::create_dir_all(sysroot.join("var/lib/pkg"))?;
+        let pid_path = sysroot.join(crate::PACKAGES_LOCK_PID_PATH);
+        let callback: Rc<RefCell<dyn Callback>> = Rc::new(RefCell::new(SilentCallback::new()));
+        let acquire = |mode, wait| PackageLock::acquire_pid_file(&sysroot, mode, wait, &callback);
+
+        let reader = acquire(LockMode::Shared, None)?;
+        assert!(!pid_path.exists());
+        drop(reader);
+
+        let writer = acquire(LockMode::Exclusive, None)?;
+        let pid = std::process::id().to_string();
+        assert!(std::fs::read_to_string(&pid_path)?.starts_with(&pid));
+        for mode in [LockMode::Shared, LockMode::Exclusive] {
+            let wait = Some(Duration::from_millis(200));
+            match acquire(mode, wait) {
+                Err(Error::PackageLocked(holder)) => assert!(holder.contains(&pid)),
+                _ => panic!("lock is not exclusive"),
+            }
+        }
+        drop(writer);
+        assert!(!pid_path.exists());
+
+        let _reader = acquire(LockMode::Shared, None)?;
+        let _reader2 = acquire(LockMode::Shared, None)?;
+
+        let _ = std::fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+}
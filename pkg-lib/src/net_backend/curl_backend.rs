@@ -11,11 +11,14 @@ use super::{DownloadBackend, DownloadError};
 
 /// Network backend using external curl
 #[derive(Clone, Default)]
-pub struct CurlBackend;
+pub struct CurlBackend {
+    /// Authorization header values per URL prefix
+    authorization: RefCell<Vec<(String, String)>>,
+}
 
 impl DownloadBackend for CurlBackend {
     fn new() -> Result<Self, DownloadError> {
-        Ok(Self)
+        Ok(Self::default())
     }
 
     fn download(
@@ -25,12 +28,29 @@ impl DownloadBackend for CurlBackend {
         writer: &mut DownloadBackendWriter,
         callback: Rc<RefCell<dyn Callback>>,
     ) -> Result<(), DownloadError> {
+        // headers are passed as config in stdin to keep credentials out of the process list
+        let mut config = String::new();
+        if let Some((_, value)) = self
+            .authorization
+            .borrow()
+            .iter()
+            .find(|(prefix, _)| remote_path.starts_with(prefix.as_str()))
+        {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            config.push_str(&format!("header = \"Authorization: {value}\"\n"));
+        }
         let mut child = Command::new("curl")
             .arg("-sSL")
+            .arg("-K")
+            .arg("-")
             .arg(remote_path)
+            .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(config.as_bytes())?;
+        }
 
         let mut stdout = child.stdout.take().ok_or_else(|| {
             DownloadError::IO(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
@@ -72,4 +92,10 @@ impl DownloadBackend for CurlBackend {
 
         Ok(())
     }
+
+    fn set_authorization(&self, url_prefix: &str, value: &str) {
+        self.authorization
+            .borrow_mut()
+            .push((url_prefix.to_string(), value.to_string()));
+    }
 }
@@ -1,7 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 use std::{
     fs::File,
-    io::{self, Write},
+    io::{self, Seek, Write},
     path::Path,
 };
 use thiserror::Error;
@@ -48,6 +48,20 @@ impl DownloadBackendWriter {
             _ => panic!("Logic error, should be a buffer going here"),
         }
     }
+    /// discard written content to write again
+    pub fn reset(&mut self) -> io::Result<()> {
+        match self {
+            DownloadBackendWriter::ToFile(file) => {
+                file.set_len(0)?;
+                file.rewind()
+            }
+            DownloadBackendWriter::ToBuf(items) => {
+                items.clear();
+                Ok(())
+            }
+        }
+    }
+
     pub fn to_inner_file(self) -> File {
         match self {
             DownloadBackendWriter::ToFile(file) => file,
@@ -93,6 +107,9 @@ pub trait DownloadBackend {
     fn file_size(&self) -> Option<usize> {
         None
     }
+
+    /// send an Authorization header value to every URL starting with url_prefix
+    fn set_authorization(&self, _url_prefix: &str, _value: &str) {}
 }
 
 #[derive(Error, Debug)]
@@ -13,6 +13,8 @@ use reqwest::blocking::Client;
 #[derive(Clone)]
 pub struct ReqwestBackend {
     client: Client,
+    /// Authorization header values per URL prefix
+    authorization: RefCell<Vec<(String, String)>>,
 }
 
 impl DownloadBackend for ReqwestBackend {
@@ -20,7 +22,10 @@ impl DownloadBackend for ReqwestBackend {
         let client = Client::builder()
             .connect_timeout(Duration::new(5, 0))
             .build()?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            authorization: RefCell::new(Vec::new()),
+        })
     }
 
     fn download(
@@ -32,7 +37,15 @@ impl DownloadBackend for ReqwestBackend {
     ) -> Result<(), DownloadError> {
         let mut callback = callback.borrow_mut();
 
-        let mut resp = self.client.get(remote_path).send()?.error_for_status()?;
+        let mut request = self.client.get(remote_path);
+        let authorization = self.authorization.borrow();
+        if let Some((_, value)) = authorization
+            .iter()
+            .find(|(prefix, _)| remote_path.starts_with(prefix.as_str()))
+        {
+            request = request.header(reqwest::header::AUTHORIZATION, value);
+        }
+        let mut resp = request.send()?.error_for_status()?;
 
         callback.download_start(remote_len.unwrap_or(0), remote_path);
 
@@ -51,4 +64,10 @@ impl DownloadBackend for ReqwestBackend {
 
         Ok(())
     }
+
+    fn set_authorization(&self, url_prefix: &str, value: &str) {
+        self.authorization
+            .borrow_mut()
+            .push((url_prefix.to_string(), value.to_string()));
+    }
 }
@@ -15,7 +15,7 @@ fn is_zero(n: &u64) -> bool {
 /// Denotes that the string is a remote key
 pub type RemoteName = String;
 
-#[derive(Default, Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Serialize, Default, Debug, Clone, PartialEq, Eq, PartialOrd)]
 pub struct RemotePackage {
     pub package: Package,
     pub remote: RemoteName,
@@ -306,12 +306,48 @@ impl Borrow<str> for PackageName {
     }
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
 pub struct PackageInfo {
     pub installed: bool,
     pub package: RemotePackage,
 }
 
+/// A file owned by an installed package, as recorded in its head
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PackageFileEntry {
+    /// path relative to the install root
+    pub path: PathBuf,
+    /// unix mode, including the file type bits
+    pub mode: u32,
+    pub size: u64,
+    /// blake3 hash in hex
+    pub blake3: String,
+}
+
+/// Kind of problem found when verifying installed files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyIssueKind {
+    Missing,
+    Modified,
+    WrongMode,
+    /// not owned by any installed package
+    Extra,
+    /// head of the package can't be read, so its files are not checked
+    /// and may be reported as extra
+    UnreadableHead,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VerifyIssue {
+    /// path relative to the install root
+    pub path: PathBuf,
+    pub kind: VerifyIssueKind,
+    /// owner of the file, none if extra
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<PackageName>,
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct SourceIdentifier {
@@ -352,6 +388,53 @@ impl Repository {
     }
 }
 
+/// A build of a package published by a remote
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RepositoryBuild {
+    pub remote: RemoteName,
+    pub blake3: String,
+}
+
+/// Repositories of every remote, merged by package name
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MergedRepository {
+    /// repository of each remote, in the order remotes are searched
+    pub remotes: Vec<(RemoteName, Repository)>,
+    /// builds offered for each package, in the order remotes are searched
+    pub packages: BTreeMap<String, Vec<RepositoryBuild>>,
+}
+
+impl MergedRepository {
+    pub fn new(remotes: Vec<(RemoteName, Repository)>) -> Self {
+        let mut packages: BTreeMap<String, Vec<RepositoryBuild>> = BTreeMap::new();
+        for (remote, repository) in &remotes {
+            for (name, blake3) in &repository.packages {
+                packages
+                    .entry(name.clone())
+                    .or_default()
+                    .push(RepositoryBuild {
+                        remote: remote.clone(),
+                        blake3: blake3.clone(),
+                    });
+            }
+        }
+        MergedRepository { remotes, packages }
+    }
+
+    /// build of the first remote offering the package
+    pub fn get(&self, package: &str) -> Option<&RepositoryBuild> {
+        self.packages.get(package)?.first()
+    }
+
+    /// build offered by a specific remote
+    pub fn get_from(&self, package: &str, remote: &str) -> Option<&RepositoryBuild> {
+        self.packages
+            .get(package)?
+            .iter()
+            .find(|build| build.remote == remote)
+    }
+}
+
 /// Errors that occur while opening or parsing [`Package`]s.
 ///
 /// These errors are unrecoverable but useful for reporting.
@@ -391,7 +474,7 @@ mod tests {
     use std::collections::BTreeMap;
 
     use crate::{
-        package::{Repository, SourceIdentifier},
+        package::{MergedRepository, Repository, SourceIdentifier},
         PackageError,
     };
 
@@ -548,6 +631,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn merge_repositories() {
+        let first = Repository {
+            packages: BTreeMap::from([("foo".into(), "a".into())]),
+            ..Default::default()
+        };
+        let second = Repository {
+            packages: BTreeMap::from([("foo".into(), "b".into()), ("bar".into(), "c".into())]),
+            ..Default::default()
+        };
+        let merged = MergedRepository::new(vec![("one".into(), first), ("two".into(), second)]);
+
+        assert_eq!(merged.packages.len(), 2);
+        assert_eq!(merged.get("foo").unwrap().remote, "one");
+        assert_eq!(merged.get_from("foo", "two").unwrap().blake3, "b");
+        assert_eq!(merged.get("bar").unwrap().remote, "two");
+        assert!(merged.get_from("bar", "one").is_none());
+    }
+
     #[test]
     fn deserialize_repository_outdated() -> Result<(), PackageError> {
         let actual = Repository::from_toml(WORKING_OUTDATED_REPOSITORY)?;
@@ -6,7 +6,7 @@ use serde_derive::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
     collections::{BTreeMap, BTreeSet},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 /// Contains current user packages state
@@ -15,6 +15,14 @@ use std::{
 pub struct PackageState {
     /// list of can't be accidentally uninstalled packages
     pub protected: BTreeSet<PackageName>,
+    /// list of protected packages from "etc/pkg/protected.d", not saved to packages.toml
+    #[serde(skip)]
+    pub protected_drop_in: BTreeSet<PackageName>,
+    /// lines of "etc/pkg/protected.d" files which are not package names, as file and line
+    #[serde(skip)]
+    pub protected_ignored: Vec<(PathBuf, String)>,
+    /// list of packages held at their installed build, skipped on update
+    pub held: BTreeSet<PackageName>,
     /// installed public keys per remote name.
     /// using pkgar_keys as a wrapper of dryoc public key.
     pub pubkeys: BTreeMap<RemoteName, RepoPublicKeyFile>,
@@ -22,11 +30,13 @@ pub struct PackageState {
     pub installed: BTreeMap<PackageName, InstallState>,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct InstallState {
     pub remote: RemoteName,
     pub blake3: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub version: String,
     pub manual: bool,
     // only useful during install
     #[serde(skip_serializing)]
@@ -46,6 +56,7 @@ impl InstallState {
         Self {
             remote,
             blake3: pkg.blake3.clone(),
+            version: pkg.version.clone(),
             manual,
             network_size: pkg.network_size,
             storage_size: pkg.storage_size,
@@ -55,26 +66,263 @@ impl InstallState {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Serialize, Default, Debug, Clone)]
 pub struct PackageList {
     pub install: Vec<PackageName>,
     pub uninstall: Vec<PackageName>,
     pub update: Vec<PackageName>,
+    /// packages that have updates available but are held
+    pub held: Vec<PackageName>,
     pub install_size: u64,
     pub network_size: u64,
     pub uninstall_size: u64,
 }
 
+/// Packages protected on a fresh installation
+const DEFAULT_PROTECTED: [&str; 8] = [
+    "kernel",
+    "base-initfs",
+    "base",
+    "ion",
+    "pkg",
+    "relibc",
+    "libgcc",
+    "libstdcxx",
+];
+
+/// Kind of change planned for a package
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageAction {
+    Install,
+    Update,
+    Uninstall,
+}
+
+/// Planned change of a package
+#[derive(Serialize, Debug, Clone)]
+pub struct PackagePlan {
+    pub name: PackageName,
+    pub action: PackageAction,
+    pub remote: RemoteName,
+    /// installed version, empty if not installed
+    pub old_version: String,
+    /// version to be installed, empty if uninstalled
+    pub new_version: String,
+    pub network_size: u64,
+    pub storage_size: u64,
+}
+
+/// File that is going to be replaced by another package
+#[derive(Serialize, Debug, Clone)]
+pub struct FileConflict {
+    pub path: PathBuf,
+    /// package owning the file, none if the file is not owned by any package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub former: Option<PackageName>,
+    pub newer: PackageName,
+}
+
+/// Pending changes computed without downloading or committing anything
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct TransactionPlan {
+    pub list: PackageList,
+    pub packages: Vec<PackagePlan>,
+    pub conflicts: Vec<FileConflict>,
+    /// packages that have no downloaded archive, which conflicts can't be predicted
+    pub unchecked: Vec<PackageName>,
+}
+
+/// Package in a dependency graph
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GraphNode {
+    pub name: PackageName,
+    pub manual: bool,
+    pub protected: bool,
+    pub storage_size: u64,
+}
+
+/// Edge from a package to one of its dependencies
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GraphEdge {
+    pub from: PackageName,
+    pub to: PackageName,
+}
+
+#[derive(Serialize, Default, Debug, Clone, PartialEq)]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl DependencyGraph {
+    /// Render as Graphviz DOT. Manual packages are bold, protected packages are filled.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph packages {\n");
+        for node in &self.nodes {
+            let mut style = Vec::new();
+            if node.manual {
+                style.push("bold");
+            }
+            if node.protected {
+                style.push("filled");
+            }
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\\n{}\"",
+                node.name,
+                node.name,
+                crate::callback::PlainCallback::format_size(node.storage_size)
+            ));
+            if !style.is_empty() {
+                dot.push_str(&format!(", style=\"{}\"", style.join(",")));
+            }
+            dot.push_str("];\n");
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!("    \"{}\" -> \"{}\";\n", edge.from, edge.to));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Node of a dependency tree, children are either dependencies or dependents
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DependencyTree {
+    pub name: PackageName,
+    pub storage_size: u64,
+    /// package is already an ancestor of this node, children are not listed
+    #[serde(skip_serializing_if = "is_false")]
+    pub cycle: bool,
+    /// children are not listed because of depth limit
+    #[serde(skip_serializing_if = "is_false")]
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<DependencyTree>,
+}
+
+impl DependencyTree {
+    /// Build a tree using `edges` which returns storage size and children of a package.
+    /// `max_depth` limits levels below the root.
+    pub fn build<F, E>(
+        name: &PackageName,
+        max_depth: Option<usize>,
+        edges: &mut F,
+    ) -> Result<Self, E>
+    where
+        F: FnMut(&PackageName) -> Result<(u64, Vec<PackageName>), E>,
+    {
+        Self::build_inner(name, max_depth, &mut Vec::new(), edges)
+    }
+
+    fn build_inner<F, E>(
+        name: &PackageName,
+        max_depth: Option<usize>,
+        ancestors: &mut Vec<PackageName>,
+        edges: &mut F,
+    ) -> Result<Self, E>
+    where
+        F: FnMut(&PackageName) -> Result<(u64, Vec<PackageName>), E>,
+    {
+        let mut node = DependencyTree {
+            name: name.clone(),
+            storage_size: 0,
+            cycle: ancestors.contains(name),
+            truncated: false,
+            children: Vec::new(),
+        };
+        if node.cycle {
+            return Ok(node);
+        }
+
+        let (storage_size, children) = edges(name)?;
+        node.storage_size = storage_size;
+        if max_depth.is_some_and(|depth| ancestors.len() >= depth) {
+            node.truncated = !children.is_empty();
+            return Ok(node);
+        }
+
+        ancestors.push(name.clone());
+        for child in &children {
+            let child = Self::build_inner(child, max_depth, ancestors, edges)?;
+            node.children.push(child);
+        }
+        ancestors.pop();
+
+        Ok(node)
+    }
+
+    /// List unique packages below the root in depth-first order
+    pub fn flatten(&self) -> Vec<&DependencyTree> {
+        let mut seen = BTreeSet::from([&self.name]);
+        let mut list = Vec::new();
+        let mut stack: Vec<&DependencyTree> = self.children.iter().rev().collect();
+        while let Some(node) = stack.pop() {
+            if !seen.insert(&node.name) {
+                continue;
+            }
+            list.push(node);
+            stack.extend(node.children.iter().rev());
+        }
+        list
+    }
+}
+
 impl PackageState {
     pub fn from_sysroot<P: AsRef<Path>>(install_path: P) -> Result<Self, PackageError> {
         let packages_path = install_path.as_ref().join(crate::PACKAGES_TOML_PATH);
 
-        match std::fs::read_to_string(&packages_path) {
+        let mut state = match std::fs::read_to_string(&packages_path) {
             Ok(toml) => {
-                toml::from_str(&toml).map_err(|e| PackageError::Parse(e, Some(packages_path)))
+                toml::from_str(&toml).map_err(|e| PackageError::Parse(e, Some(packages_path)))?
+            }
+            Err(_) => PackageState::default(),
+        };
+        state.read_protected_dir(install_path.as_ref())?;
+
+        Ok(state)
+    }
+
+    /// read package names in [install_path]/etc/pkg/protected.d, one package per line.
+    /// Lines which are not package names are kept in protected_ignored.
+    fn read_protected_dir(&mut self, install_path: &Path) -> Result<(), PackageError> {
+        let protected_path = install_path.join(crate::PACKAGES_PROTECTED_DIR);
+
+        let Ok(entries) = std::fs::read_dir(&protected_path) else {
+            return Ok(());
+        };
+        for entry_res in entries {
+            let entry = entry_res.map_err(|err| {
+                PackageError::FileError(err.raw_os_error(), protected_path.clone())
+            })?;
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let data = std::fs::read_to_string(&path)
+                .map_err(|err| PackageError::FileError(err.raw_os_error(), path.clone()))?;
+            for line in data.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // a typo must not lock out the commands needed to fix it
+                match PackageName::new(line) {
+                    Ok(name) => {
+                        self.protected_drop_in.insert(name);
+                    }
+                    Err(_) => self
+                        .protected_ignored
+                        .push((path.clone(), line.to_string())),
+                }
             }
-            Err(_) => Ok(PackageState::default()),
         }
+
+        Ok(())
     }
 
     pub fn from_toml(text: &str) -> Result<Self, PackageError> {
@@ -88,11 +336,7 @@ impl PackageState {
 
     pub fn to_sysroot<P: AsRef<Path>>(&self, install_path: P) -> Result<(), std::io::Error> {
         let packages_path = install_path.as_ref().join(crate::PACKAGES_TOML_PATH);
-        let packages_dir = packages_path.parent().unwrap();
-        if !packages_dir.is_dir() {
-            std::fs::create_dir_all(packages_dir)?;
-        }
-        std::fs::write(&packages_path, self.to_toml())
+        crate::journal::write_atomic(&packages_path, &self.to_toml())
     }
 
     // mutably add valid packages to the graph.
@@ -155,22 +399,18 @@ impl PackageState {
                 continue;
             }
 
-            let (manual, dependents, remote) = if let Some(existing) = self.installed.get(&pkg.name)
-            {
-                (
-                    existing.manual,
-                    existing.dependents.clone(),
-                    existing.remote.clone(),
-                )
+            let (manual, dependents) = if let Some(existing) = self.installed.get(&pkg.name) {
+                (existing.manual, existing.dependents.clone())
             } else {
                 (
                     false,
                     unsatisfied_deps.remove(&pkg.name).unwrap_or_default(),
-                    rpkg.remote.to_string(),
                 )
             };
 
-            let new_state = InstallState::from_package(pkg, remote, manual, dependents);
+            // the build is recorded with the remote it's taken from
+            let new_state =
+                InstallState::from_package(pkg, rpkg.remote.to_string(), manual, dependents);
 
             self.installed.insert(pkg.name.clone(), new_state);
 
@@ -205,7 +445,7 @@ impl PackageState {
         let mut packages_to_remove = packages.to_vec();
 
         // Filter out protected packages. Caller can wipe out the list beforehand to skip this behaviour.
-        packages_to_remove.retain(|name| !self.protected.contains(name));
+        packages_to_remove.retain(|name| !self.is_protected(name));
 
         let remove_set: BTreeSet<&PackageName> = packages_to_remove.iter().collect();
         let mut safe_to_remove = Vec::new();
@@ -225,7 +465,10 @@ impl PackageState {
                 .iter()
                 .cloned()
                 .filter(|dep| {
-                    !remove_set.contains(dep) && self.installed.get(dep).is_some_and(|p| !p.manual)
+                    !remove_set.contains(dep)
+                        && self.installed.get(dep).is_some_and(|p| {
+                            !p.manual && p.dependents.iter().all(|d| remove_set.contains(d))
+                        })
                 })
                 .collect();
 
@@ -239,6 +482,8 @@ impl PackageState {
         }
 
         for name in safe_to_remove {
+            // a hold doesn't outlive the package
+            self.held.remove(&name);
             if let Some(state) = self.installed.remove(&name) {
                 for dep_name in &state.dependencies {
                     if let Some(dep_state) = self.installed.get_mut(dep_name) {
@@ -307,6 +552,128 @@ impl PackageState {
         self.installed.keys().cloned().collect()
     }
 
+    /// Returns list of installed packages that are marked manually installed or not.
+    pub fn get_marked_list(&self, manual: bool) -> Vec<PackageName> {
+        self.installed
+            .iter()
+            .filter(|(_, state)| state.manual == manual)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Returns list of automatically installed packages that are no longer needed,
+    /// including dependencies that are only needed by those packages.
+    /// Protected packages are never listed.
+    pub fn get_orphans(&self) -> Vec<PackageName> {
+        let mut orphans = BTreeSet::new();
+
+        loop {
+            let mut has_new_orphans = false;
+
+            for (name, state) in &self.installed {
+                if state.manual || orphans.contains(name) || self.is_protected(name) {
+                    continue;
+                }
+                if state.dependents.iter().all(|dep| orphans.contains(dep)) {
+                    orphans.insert(name.clone());
+                    has_new_orphans = true;
+                }
+            }
+
+            if !has_new_orphans {
+                break;
+            }
+        }
+
+        orphans.into_iter().collect()
+    }
+
+    /// Restore install state of packages, or uninstall packages with none state.
+    /// Dependents are recomputed, and fails if an installed package would miss its dependency.
+    pub fn restore(
+        &mut self,
+        packages: Vec<(PackageName, Option<InstallState>)>,
+    ) -> Result<(), PackageError> {
+        let mut installed = self.installed.clone();
+        for (name, state) in packages {
+            match state {
+                Some(state) => installed.insert(name, state),
+                None => installed.remove(&name),
+            };
+        }
+
+        for state in installed.values_mut() {
+            state.dependents.clear();
+        }
+        let edges: Vec<(PackageName, PackageName)> = installed
+            .iter()
+            .flat_map(|(name, state)| {
+                state
+                    .dependencies
+                    .iter()
+                    .map(move |dep| (dep.clone(), name.clone()))
+            })
+            .collect();
+        for (dependency, dependent) in edges {
+            let Some(state) = installed.get_mut(&dependency) else {
+                return Err(PackageError::DependencyInvalid(dependent));
+            };
+            state.dependents.insert(dependent);
+        }
+
+        self.installed = installed;
+        Ok(())
+    }
+
+    /// Returns graph of installed packages and their dependencies
+    pub fn get_dependency_graph(&self) -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (name, state) in &self.installed {
+            graph.nodes.push(GraphNode {
+                name: name.clone(),
+                manual: state.manual,
+                protected: self.is_protected(name),
+                storage_size: state.storage_size,
+            });
+            for dependency in &state.dependencies {
+                graph.edges.push(GraphEdge {
+                    from: name.clone(),
+                    to: dependency.clone(),
+                });
+            }
+        }
+        graph
+    }
+
+    /// Returns every dependency path from a manually installed or protected package
+    /// down to the given package. Empty if not installed or nothing needs it.
+    pub fn why(&self, package: &PackageName) -> Vec<Vec<PackageName>> {
+        let mut paths = Vec::new();
+        let mut chain = vec![package.clone()];
+        self.why_inner(&mut chain, &mut paths);
+        paths
+    }
+
+    // walks dependents upwards, chain is in reverse order
+    fn why_inner(&self, chain: &mut Vec<PackageName>, paths: &mut Vec<Vec<PackageName>>) {
+        let name = chain.last().unwrap();
+        let Some(state) = self.installed.get(name) else {
+            return;
+        };
+        if state.manual || self.is_protected(name) {
+            paths.push(chain.iter().rev().cloned().collect());
+            return;
+        }
+        for dependent in &state.dependents {
+            if chain.contains(dependent) {
+                continue; // cycle
+            }
+            chain.push(dependent.clone());
+            self.why_inner(chain, paths);
+            chain.pop();
+        }
+    }
+
     /// Mark packages manually installed or not. Returns list of changed packages.
     /// PackageState are not marked automatically in any install mechanism.
     pub fn mark_as_manual(&mut self, manual: bool, packages: &[PackageName]) -> Vec<PackageName> {
@@ -323,24 +690,62 @@ impl PackageState {
         }
         marked
     }
+
+    /// Check if package can't be accidentally uninstalled
+    pub fn is_protected(&self, package: &PackageName) -> bool {
+        self.protected.contains(package) || self.protected_drop_in.contains(package)
+    }
+
+    /// Mark packages protected or not. Returns list of changed packages.
+    /// Packages protected by "etc/pkg/protected.d" stay protected.
+    pub fn mark_as_protected(
+        &mut self,
+        protected: bool,
+        packages: &[PackageName],
+    ) -> Vec<PackageName> {
+        let mut marked = Vec::new();
+
+        for package in packages {
+            let changed = if protected {
+                self.protected.insert(package.clone())
+            } else {
+                self.protected.remove(package)
+            };
+            if changed {
+                marked.push(package.clone());
+            }
+        }
+        marked
+    }
+
+    /// Mark packages held or not. Returns list of changed packages.
+    pub fn mark_as_held(&mut self, held: bool, packages: &[PackageName]) -> Vec<PackageName> {
+        let mut marked = Vec::new();
+
+        for package in packages {
+            let changed = if held {
+                self.held.insert(package.clone())
+            } else {
+                self.held.remove(package)
+            };
+            if changed {
+                marked.push(package.clone());
+            }
+        }
+        marked
+    }
 }
 
 impl Default for PackageState {
     fn default() -> Self {
         Self {
-            // TODO: Hardcoded
-            protected: vec![
-                PackageName::new("kernel").unwrap(),
-                PackageName::new("base-initfs").unwrap(),
-                PackageName::new("base").unwrap(),
-                PackageName::new("ion").unwrap(),
-                PackageName::new("pkg").unwrap(),
-                PackageName::new("relibc").unwrap(),
-                PackageName::new("libgcc").unwrap(),
-                PackageName::new("libstdcxx").unwrap(),
-            ]
-            .into_iter()
-            .collect(),
+            protected: DEFAULT_PROTECTED
+                .iter()
+                .map(|name| PackageName::new(*name).unwrap())
+                .collect(),
+            protected_drop_in: Default::default(),
+            protected_ignored: Default::default(),
+            held: Default::default(),
             pubkeys: Default::default(),
             installed: Default::default(),
         }
@@ -386,6 +791,9 @@ mod tests {
     fn mock_empty_db() -> PackageState {
         PackageState {
             protected: BTreeSet::new(),
+            protected_drop_in: BTreeSet::new(),
+            protected_ignored: Vec::new(),
+            held: BTreeSet::new(),
             pubkeys: BTreeMap::new(),
             installed: BTreeMap::new(),
         }
@@ -407,6 +815,8 @@ mod tests {
 
         assert_eq!(db.mark_as_manual(true, &names), vec![cpkg("nano")]);
         assert_eq!(db.installed[&cpkg("nano")].manual, true);
+        assert_eq!(db.get_marked_list(true), names);
+        assert_eq!(db.get_marked_list(false), vec![]);
     }
 
     #[test]
@@ -508,6 +918,220 @@ mod tests {
         assert_eq!(db.get_installed_list(), vec![cpkg("libiconv")]);
     }
 
+    #[test]
+    fn test_uninstall_keeps_shared_dependencies() {
+        let mut db = mock_empty_db();
+
+        let gettext = mock_package("gettext", vec!["libiconv"]);
+        let git = mock_package("git", vec!["libiconv"]);
+        let libiconv = mock_package("libiconv", vec![]);
+        db.install(&[gettext, git, libiconv]);
+        db.mark_as_manual(true, &vec![cpkg("gettext"), cpkg("git")]);
+        let result = db.uninstall(&[cpkg("gettext")]);
+        assert_eq!(result, vec![]);
+        assert_eq!(db.get_installed_list(), vec![cpkg("git"), cpkg("libiconv")]);
+    }
+
+    #[test]
+    fn test_orphans() {
+        let mut db = mock_empty_db();
+
+        let bash = mock_package("bash", vec!["readline"]);
+        let readline = mock_package("readline", vec!["ncurses"]);
+        let ncurses = mock_package("ncurses", vec![]);
+        let nano = mock_package("nano", vec!["ncurses"]);
+        let kernel = mock_package("kernel", vec![]);
+        db.install(&[bash, readline, ncurses, nano, kernel]);
+        db.mark_as_manual(true, &vec![cpkg("bash"), cpkg("nano")]);
+        db.protected.insert(cpkg("kernel"));
+        assert_eq!(db.get_orphans(), vec![]);
+
+        db.mark_as_manual(false, &vec![cpkg("bash")]);
+        assert_eq!(db.get_orphans(), vec![cpkg("bash"), cpkg("readline")]);
+
+        let result = db.uninstall(&db.get_orphans());
+        assert_eq!(result, vec![]);
+        assert_eq!(
+            db.get_installed_list(),
+            vec![cpkg("kernel"), cpkg("nano"), cpkg("ncurses")]
+        );
+        assert_eq!(db.get_orphans(), vec![]);
+    }
+
+    #[test]
+    fn test_why() {
+        let mut db = mock_empty_db();
+
+        let bash = mock_package("bash", vec!["readline", "ncurses"]);
+        let readline = mock_package("readline", vec!["ncurses"]);
+        let ncurses = mock_package("ncurses", vec![]);
+        let kernel = mock_package("kernel", vec![]);
+        db.install(&[bash, readline, ncurses, kernel]);
+        db.mark_as_manual(true, &vec![cpkg("bash")]);
+        db.protected.insert(cpkg("kernel"));
+
+        assert_eq!(
+            db.why(&cpkg("ncurses")),
+            vec![
+                vec![cpkg("bash"), cpkg("ncurses")],
+                vec![cpkg("bash"), cpkg("readline"), cpkg("ncurses")],
+            ]
+        );
+        assert_eq!(db.why(&cpkg("bash")), vec![vec![cpkg("bash")]]);
+        assert_eq!(db.why(&cpkg("kernel")), vec![vec![cpkg("kernel")]]);
+
+        db.mark_as_manual(false, &vec![cpkg("bash")]);
+        assert_eq!(db.why(&cpkg("ncurses")), Vec::<Vec<PackageName>>::new());
+    }
+
+    #[test]
+    fn test_dependency_tree() {
+        let graph: BTreeMap<PackageName, Vec<PackageName>> = BTreeMap::from([
+            (cpkg("bash"), vec![cpkg("readline"), cpkg("ncurses")]),
+            (cpkg("readline"), vec![cpkg("ncurses")]),
+            (cpkg("ncurses"), vec![cpkg("bash")]),
+        ]);
+        let mut edges = |name: &PackageName| -> Result<(u64, Vec<PackageName>), PackageError> {
+            Ok((100, graph[name].clone()))
+        };
+
+        let tree = DependencyTree::build(&cpkg("bash"), None, &mut edges).unwrap();
+        let readline = &tree.children[0];
+        assert_eq!(readline.name, cpkg("readline"));
+        assert_eq!(readline.children[0].children[0].name, cpkg("bash"));
+        assert!(readline.children[0].children[0].cycle);
+        assert_eq!(
+            tree.flatten()
+                .into_iter()
+                .map(|n| n.name.clone())
+                .collect::<Vec<_>>(),
+            vec![cpkg("readline"), cpkg("ncurses")]
+        );
+
+        let tree = DependencyTree::build(&cpkg("bash"), Some(1), &mut edges).unwrap();
+        assert!(tree
+            .children
+            .iter()
+            .all(|c| c.truncated && c.children.is_empty()));
+        assert!(!tree.truncated);
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let mut db = mock_empty_db();
+        db.install(&[
+            mock_package("bash", vec!["readline"]),
+            mock_package("readline", vec![]),
+        ]);
+        db.mark_as_manual(true, &vec![cpkg("bash")]);
+
+        let graph = db.get_dependency_graph();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(
+            graph.edges,
+            vec![GraphEdge {
+                from: cpkg("bash"),
+                to: cpkg("readline"),
+            }]
+        );
+        let dot = graph.to_dot();
+        assert!(dot.contains("\"bash\" [label=\"bash\\n1000.00 B\", style=\"bold\"];"));
+        assert!(dot.contains("\"bash\" -> \"readline\";"));
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut db = mock_empty_db();
+        db.install(&[
+            mock_package("bash", vec!["readline"]),
+            mock_package("readline", vec![]),
+        ]);
+        let old_readline = db.installed[&cpkg("readline")].clone();
+
+        assert!(matches!(
+            db.restore(vec![(cpkg("readline"), None)]),
+            Err(PackageError::DependencyInvalid(_))
+        ));
+        assert_eq!(
+            db.get_installed_list(),
+            vec![cpkg("bash"), cpkg("readline")]
+        );
+
+        db.restore(vec![(cpkg("bash"), None), (cpkg("readline"), None)])
+            .unwrap();
+        assert_eq!(db.get_installed_list(), vec![]);
+
+        let mut readline = old_readline.clone();
+        readline.dependents = BTreeSet::from([cpkg("nano")]);
+        db.restore(vec![(cpkg("readline"), Some(readline))])
+            .unwrap();
+        assert_eq!(db.installed[&cpkg("readline")].dependents, BTreeSet::new());
+        assert_eq!(db.installed[&cpkg("readline")].blake3, old_readline.blake3);
+    }
+
+    #[test]
+    fn test_protected_drop_in() -> Result<(), PackageError> {
+        let sysroot = std::env::temp_dir().join("pkg_test_protected_drop_in");
+        let protected_dir = sysroot.join(crate::PACKAGES_PROTECTED_DIR);
+        let _ = std::fs::remove_dir_all(&sysroot);
+        std::fs::create_dir_all(&protected_dir).unwrap();
+        std::fs::write(
+            protected_dir.join("10_display"),
+            "# display server\norbital\n",
+        )
+        .unwrap();
+        std::fs::write(protected_dir.join("20_typo"), "compositor/\nnetstack\n").unwrap();
+
+        let mut db = PackageState::from_sysroot(&sysroot)?;
+        assert!(db.is_protected(&cpkg("kernel")));
+        assert!(db.is_protected(&cpkg("orbital")));
+        // invalid names are skipped
+        assert!(db.is_protected(&cpkg("netstack")));
+        assert_eq!(db.protected_drop_in.len(), 2);
+        assert_eq!(
+            db.protected_ignored,
+            vec![(protected_dir.join("20_typo"), "compositor/".to_string())]
+        );
+
+        db.install(&[mock_package("orbital", vec![])]);
+        assert_eq!(db.uninstall(&[cpkg("orbital")]), vec![]);
+        assert_eq!(db.get_installed_list(), vec![cpkg("orbital")]);
+
+        // drop-in files are not merged into packages.toml
+        assert_eq!(db.mark_as_protected(false, &[cpkg("orbital")]), vec![]);
+        db.to_sysroot(&sysroot).unwrap();
+        let toml = std::fs::read_to_string(sysroot.join(crate::PACKAGES_TOML_PATH)).unwrap();
+        let db = PackageState::from_toml(&toml)?;
+        assert!(!db.is_protected(&cpkg("orbital")));
+
+        std::fs::remove_dir_all(&sysroot).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_as_held() {
+        let mut db = mock_empty_db();
+
+        let names = vec![cpkg("gcc13"), cpkg("rust")];
+        assert_eq!(db.mark_as_held(true, &names), names);
+        assert_eq!(db.mark_as_held(true, &names), vec![]);
+        assert_eq!(db.mark_as_held(false, &names[..1]), vec![cpkg("gcc13")]);
+        assert_eq!(db.held, vec![cpkg("rust")].into_iter().collect());
+
+        let db = PackageState::from_toml(&db.to_toml()).unwrap();
+        assert_eq!(db.held, vec![cpkg("rust")].into_iter().collect());
+    }
+
+    #[test]
+    fn test_uninstall_held() {
+        let mut db = mock_empty_db();
+        db.install(&[mock_package("nano", vec![]), mock_package("vim", vec![])]);
+        db.mark_as_held(true, &[cpkg("nano"), cpkg("vim")]);
+
+        assert_eq!(db.uninstall(&[cpkg("nano")]), vec![]);
+        assert_eq!(db.held, vec![cpkg("vim")].into_iter().collect());
+    }
+
     #[test]
     fn test_toml_integration() -> Result<(), PackageError> {
         const TOML_DATA: &str = r#"
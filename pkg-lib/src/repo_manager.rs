@@ -1,18 +1,16 @@
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::fs::File;
 use std::path::Path;
 use std::rc::Rc;
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, process};
 
 use crate::callback::Callback;
-#[cfg(feature = "library")]
-use crate::net_backend::DownloadError;
-use crate::net_backend::{DownloadBackend, DownloadBackendWriter};
+use crate::net_backend::{DownloadBackend, DownloadBackendWriter, DownloadError};
 use crate::package::RemoteName;
-use crate::{backend::Error, package::PackageError, PackageName};
-use crate::{DOWNLOAD_DIR, PACKAGES_REMOTE_DIR};
+use crate::{backend::Error, package::PackageError, PackageName, PackageState};
+use crate::{DOWNLOAD_DIR, PACKAGES_CACHE_DIR, PACKAGES_PREFERENCES_PATH, PACKAGES_REMOTE_DIR};
 use serde_derive::{Deserialize, Serialize};
 /// Remote package management
 pub struct RepoManager {
@@ -24,6 +22,8 @@ pub struct RepoManager {
     pub remote_map: BTreeMap<RemoteName, RemotePath>,
     pub download_path: PathBuf,
     pub download_backend: Rc<Box<dyn DownloadBackend>>,
+    /// remote priorities and package pins
+    pub preferences: RepoPreferences,
 
     pub callback: Rc<RefCell<dyn Callback>>,
 }
@@ -35,6 +35,7 @@ impl Debug for RepoManager {
             .field("locals", &self.locals)
             .field("remote_map", &self.remote_map)
             .field("download_path", &self.download_path)
+            .field("preferences", &self.preferences)
             .finish()
     }
 }
@@ -47,6 +48,7 @@ impl Clone for RepoManager {
             remote_map: self.remote_map.clone(),
             download_path: self.download_path.clone(),
             download_backend: self.download_backend.clone(),
+            preferences: self.preferences.clone(),
             callback: self.callback.clone(),
         }
     }
@@ -55,7 +57,7 @@ impl Clone for RepoManager {
 /// same as pkgar_core::PublicKey
 pub type RepoPublicKey = [u8; 32];
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 
 /// same as pkgar_keys::PublicKeyFile
 pub struct RepoPublicKeyFile {
@@ -93,15 +95,261 @@ pub struct RemotePath {
     pub name: RemoteName,
     /// Embedded public key, lazily loaded
     pub pubkey: Option<RepoPublicKey>,
+    /// URL which path and pubpath start with
+    pub url: String,
+    /// URLs serving the same files as url, tried in order when it fails
+    pub mirrors: Vec<String>,
+    /// Higher is searched first, unless overridden by preferences
+    pub priority: i64,
+    /// Hex blake3 hash the public key must have when downloaded
+    pub pubkey_fingerprint: Option<String>,
+    /// Why credentials of the remote couldn't be read, downloads from it fail with it
+    pub auth_error: Option<String>,
 }
 
 impl RemotePath {
     pub fn is_local(&self) -> bool {
         self.pubpath.is_empty()
     }
+
+    /// URL of a file of this remote, followed by the same file on each mirror
+    pub fn with_mirrors(&self, remote_path: &str) -> Vec<String> {
+        let mut urls = vec![remote_path.to_string()];
+        if let Some(file) = remote_path.strip_prefix(&self.url) {
+            urls.extend(self.mirrors.iter().map(|m| format!("{m}{file}")));
+        }
+        urls
+    }
+}
+
+/// Repository declared in [install_path]/etc/pkg.d
+#[derive(Clone, Debug, Serialize)]
+pub struct RepoConfig {
+    /// Unique ID
+    pub name: RemoteName,
+    /// URL/Path to repository
+    pub url: String,
+    /// File declaring this repository
+    pub file: PathBuf,
+    pub local: bool,
+    pub enabled: bool,
+    /// Public key is already downloaded or installed
+    pub pubkey_cached: bool,
+    /// Higher is searched first
+    pub priority: i64,
+    pub mirrors: Vec<String>,
+    /// Target triple used instead of the installation one
+    pub target: Option<String>,
+    /// Credentials are configured
+    pub auth: bool,
+}
+
+/// Repository file in [install_path]/etc/pkg.d ending with ".toml".
+/// Other files are read as one URL per line.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RepoFile {
+    #[serde(rename = "repo")]
+    pub repos: Vec<RepoFileEntry>,
+}
+
+/// A repository declared in a repository file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RepoFileEntry {
+    /// Unique ID, host of the URL if empty
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub url: String,
+    /// URLs serving the same repository, tried in order when url fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// Higher is searched first, overridden by etc/pkg/preferences.toml
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i64,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    /// Hex public key, used instead of downloading it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    /// Hex blake3 hash of the public key, checked when it's downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey_fingerprint: Option<String>,
+    /// Target triple used instead of the installation one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<RepoAuth>,
+}
+
+fn is_zero(n: &i64) -> bool {
+    *n == 0
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+impl RepoFileEntry {
+    /// entry of a legacy repository file line
+    pub fn from_url(url: &str, enabled: bool) -> Self {
+        RepoFileEntry {
+            name: String::new(),
+            url: url.to_string(),
+            mirrors: Vec::new(),
+            priority: 0,
+            enabled,
+            pubkey: None,
+            pubkey_fingerprint: None,
+            target: None,
+            auth: None,
+        }
+    }
+
+    /// Unique ID of the repository
+    pub fn remote_name(&self) -> Result<RemoteName, Error> {
+        if !self.name.is_empty() {
+            return Ok(self.name.clone());
+        }
+        RepoManager::extract_host(&self.url)
+            .map(str::to_string)
+            .ok_or_else(|| Error::RepoPathInvalid(self.url.clone()))
+    }
+}
+
+/// Credentials sent to a repository and its mirrors
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RepoAuth {
+    /// Basic authentication
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// File containing the password, inside the installation root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// Bearer token authentication
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// File containing the token, inside the installation root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+}
+
+impl RepoAuth {
+    /// value of Authorization header, none if no credentials are set
+    pub fn authorization(&self, install_path: &Path) -> Result<Option<String>, Error> {
+        let read_secret = |value: &Option<String>, file: &Option<PathBuf>| match (value, file) {
+            (Some(value), _) => Ok(Some(value.clone())),
+            (None, Some(file)) => {
+                let path = install_path.join(file.strip_prefix("/").unwrap_or(file));
+                let secret = fs::read_to_string(&path)
+                    .map_err(|err| PackageError::FileError(err.raw_os_error(), path))?;
+                Ok::<_, Error>(Some(secret.trim().to_string()))
+            }
+            (None, None) => Ok(None),
+        };
+
+        if let Some(token) = read_secret(&self.token, &self.token_file)? {
+            return Ok(Some(format!("Bearer {token}")));
+        }
+        if let Some(username) = &self.username {
+            let password = read_secret(&self.password, &self.password_file)?.unwrap_or_default();
+            let credentials = base64_encode(format!("{username}:{password}").as_bytes());
+            return Ok(Some(format!("Basic {credentials}")));
+        }
+        Ok(None)
+    }
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Repository preferences in [install_path]/etc/pkg/preferences.toml
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RepoPreferences {
+    /// priority per remote name, higher is searched first. Overrides repository files.
+    pub priority: BTreeMap<RemoteName, i64>,
+    /// remote name per package name or glob pattern, pinned packages only come from it
+    pub pin: BTreeMap<String, RemoteName>,
+}
+
+impl RepoPreferences {
+    pub fn from_sysroot(install_path: &Path) -> Result<Self, PackageError> {
+        let path = install_path.join(PACKAGES_PREFERENCES_PATH);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let toml = fs::read_to_string(&path)
+            .map_err(|err| PackageError::FileError(err.raw_os_error(), path.clone()))?;
+        toml::from_str(&toml).map_err(|err| PackageError::Parse(err, Some(path)))
+    }
+
+    pub fn get_priority(&self, remote: &str) -> Option<i64> {
+        self.priority.get(remote).copied()
+    }
+
+    /// remote a package is pinned to. An exact name wins over patterns, then the longest pattern.
+    pub fn get_pin(&self, package: &str) -> Option<&RemoteName> {
+        if let Some(remote) = self.pin.get(package) {
+            return Some(remote);
+        }
+        self.pin
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, package))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, remote)| remote)
+    }
+}
+
+/// match a name with a pattern of `*` (any characters) and `?` (one character)
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of last star and the name position it's matched up to
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // let the star take one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 const PUB_TOML: &str = "id_ed25519.pub.toml";
+/// Prefix of a repository line that is temporarily disabled
+const DISABLED_PREFIX: &str = "# disabled:";
 
 impl RepoManager {
     pub fn new(
@@ -113,6 +361,7 @@ impl RepoManager {
             locals: Vec::new(),
             download_path: DOWNLOAD_DIR.into(),
             download_backend: Rc::new(download_backend),
+            preferences: RepoPreferences::default(),
             callback: callback,
             remote_map: BTreeMap::new(),
         }
@@ -145,19 +394,248 @@ impl RepoManager {
         }
         repo_files.sort();
         for repo_file in repo_files {
-            let data = fs::read_to_string(repo_file)?;
-            for line in data.lines() {
-                if !line.starts_with('#') {
-                    self.add_remote(line.trim(), target)?;
+            for repo in Self::read_repo_file(&repo_file)? {
+                if repo.enabled {
+                    self.add_repo(&repo, target, install_path)?;
                 }
             }
         }
         // optional local path
         let local_pub_path = install_path.join("pkg");
         let _ = self.add_local("installer_key", "", target, &local_pub_path);
+        self.preferences = RepoPreferences::from_sysroot(install_path)?;
+        Ok(())
+    }
+
+    /// read all repositories declared in [install_path]/etc/pkg.d, including disabled ones.
+    /// Downloaded public keys are looked up in cache_dir, [install_path]/var/cache/pkg if none.
+    pub fn read_repo_configs(
+        install_path: &Path,
+        cache_dir: Option<&Path>,
+    ) -> Result<Vec<RepoConfig>, Error> {
+        let cache_dir = cache_dir
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| install_path.join(PACKAGES_CACHE_DIR));
+        let repos_path = install_path.join(PACKAGES_REMOTE_DIR);
+        let mut repo_files = Vec::new();
+        if repos_path.is_dir() {
+            for entry_res in fs::read_dir(&repos_path)? {
+                let path = entry_res?.path();
+                if path.is_file() {
+                    repo_files.push(path);
+                }
+            }
+        }
+        repo_files.sort();
+
+        let pubkeys = PackageState::from_sysroot(install_path)?.pubkeys;
+        let preferences = RepoPreferences::from_sysroot(install_path)?;
+        let mut configs = Vec::new();
+        for repo_file in repo_files {
+            for repo in Self::read_repo_file(&repo_file)? {
+                let name = repo.remote_name()?;
+                let pubkey_cached = repo.pubkey.is_some()
+                    || pubkeys.contains_key(&name)
+                    || cache_dir.join(format!("pub_key_{}.toml", name)).is_file();
+                configs.push(RepoConfig {
+                    priority: preferences.get_priority(&name).unwrap_or(repo.priority),
+                    name,
+                    url: repo.url,
+                    file: repo_file.clone(),
+                    local: false,
+                    enabled: repo.enabled,
+                    pubkey_cached,
+                    mirrors: repo.mirrors,
+                    target: repo.target,
+                    auth: repo.auth.is_some(),
+                });
+            }
+        }
+
+        // optional local path, see update_remotes()
+        let local_pub_path = install_path.join("pkg").join(PUB_TOML);
+        if local_pub_path.is_file() {
+            configs.push(RepoConfig {
+                name: "installer_key".into(),
+                url: install_path.join("pkg").to_string_lossy().to_string(),
+                file: local_pub_path,
+                local: true,
+                enabled: true,
+                pubkey_cached: true,
+                priority: preferences.get_priority("installer_key").unwrap_or(0),
+                mirrors: Vec::new(),
+                target: None,
+                auth: false,
+            });
+        }
+
+        Ok(configs)
+    }
+
+    fn find_repo_config(install_path: &Path, repo: &str) -> Result<RepoConfig, Error> {
+        Self::read_repo_configs(install_path, None)?
+            .into_iter()
+            .find(|r| !r.local && (r.name == repo || r.url == repo))
+            .ok_or_else(|| Error::RepoNotConfigured(repo.into()))
+    }
+
+    /// rewrite lines of a repository file, deletes the file if no repository is left
+    fn rewrite_repo_file<F>(path: &Path, mut map_line: F) -> Result<(), Error>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let data = fs::read_to_string(path)?;
+        let lines: Vec<String> = data.lines().filter_map(&mut map_line).collect();
+        let is_repo = |line: &String| {
+            let line = line.trim();
+            line.starts_with(DISABLED_PREFIX) || !(line.is_empty() || line.starts_with('#'))
+        };
+        if !lines.iter().any(is_repo) {
+            fs::remove_file(path)?;
+        } else {
+            fs::write(path, lines.join("\n") + "\n")?;
+        }
+        Ok(())
+    }
+
+    fn is_toml_file(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "toml")
+    }
+
+    /// read repositories of a file in [install_path]/etc/pkg.d, including disabled ones
+    pub fn read_repo_file(path: &Path) -> Result<Vec<RepoFileEntry>, Error> {
+        let data = fs::read_to_string(path)?;
+        if Self::is_toml_file(path) {
+            let file: RepoFile = toml::from_str(&data)
+                .map_err(|err| PackageError::Parse(err, Some(path.to_path_buf())))?;
+            return Ok(file.repos);
+        }
+
+        let mut repos = Vec::new();
+        for line in data.lines() {
+            let (url, enabled) = match line.strip_prefix(DISABLED_PREFIX) {
+                Some(url) => (url.trim(), false),
+                None if line.starts_with('#') => continue,
+                None => (line.trim(), true),
+            };
+            if !url.is_empty() {
+                repos.push(RepoFileEntry::from_url(url, enabled));
+            }
+        }
+        Ok(repos)
+    }
+
+    /// edit a repository of a TOML repository file in place, keeping comments and formatting.
+    /// The repository is removed if map_repo returns false, the file is deleted if nothing left.
+    fn rewrite_toml_repo_file<F>(path: &Path, name: &str, mut map_repo: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut toml_edit::Table) -> bool,
+    {
+        let data = fs::read_to_string(path)?;
+        let mut doc: toml_edit::DocumentMut = data
+            .parse()
+            .map_err(|err| Error::RepoPathInvalid(format!("{}: {err}", path.display())))?;
+        let Some(repos) = doc
+            .get_mut("repo")
+            .and_then(toml_edit::Item::as_array_of_tables_mut)
+        else {
+            return Err(Error::RepoNotConfigured(name.into()));
+        };
+        let mut i = 0;
+        while let Some(repo) = repos.get_mut(i) {
+            if Self::toml_repo_name(repo).as_deref() == Some(name) && !map_repo(repo) {
+                repos.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        if repos.is_empty() {
+            fs::remove_file(path)?;
+        } else {
+            fs::write(path, doc.to_string())?;
+        }
         Ok(())
     }
 
+    // same as RepoFileEntry::remote_name() of a TOML table
+    fn toml_repo_name(repo: &toml_edit::Table) -> Option<String> {
+        match repo.get("name").and_then(|n| n.as_str()) {
+            Some(name) if !name.is_empty() => Some(name.to_string()),
+            _ => repo
+                .get("url")
+                .and_then(|u| u.as_str())
+                .and_then(Self::extract_host)
+                .map(str::to_string),
+        }
+    }
+
+    /// add a repository to [install_path]/etc/pkg.d as a new file. Returns path of the file.
+    pub fn add_repo_config(install_path: &Path, url: &str) -> Result<PathBuf, Error> {
+        let url = url.trim().trim_end_matches('/');
+        let host = Self::extract_host(url).ok_or_else(|| Error::RepoPathInvalid(url.into()))?;
+        if Self::find_repo_config(install_path, host).is_ok() {
+            return Err(Error::RepoExists(host.into()));
+        }
+
+        let repos_path = install_path.join(PACKAGES_REMOTE_DIR);
+        fs::create_dir_all(&repos_path)?;
+        let path = repos_path.join(format!("50_{}", host));
+        fs::write(&path, format!("{}\n", url))?;
+        Ok(path)
+    }
+
+    /// remove a repository by its name or URL from [install_path]/etc/pkg.d
+    pub fn remove_repo_config(install_path: &Path, repo: &str) -> Result<RepoConfig, Error> {
+        let config = Self::find_repo_config(install_path, repo)?;
+        if Self::is_toml_file(&config.file) {
+            Self::rewrite_toml_repo_file(&config.file, &config.name, |_| false)?;
+            return Ok(config);
+        }
+        Self::rewrite_repo_file(&config.file, |line| {
+            let url = line.strip_prefix(DISABLED_PREFIX).unwrap_or(line).trim();
+            if url == config.url {
+                None
+            } else {
+                Some(line.to_string())
+            }
+        })?;
+        Ok(config)
+    }
+
+    /// enable or disable a repository by its name or URL without removing it
+    pub fn set_repo_enabled(
+        install_path: &Path,
+        repo: &str,
+        enabled: bool,
+    ) -> Result<RepoConfig, Error> {
+        let mut config = Self::find_repo_config(install_path, repo)?;
+        if Self::is_toml_file(&config.file) {
+            Self::rewrite_toml_repo_file(&config.file, &config.name, |repo| {
+                if enabled {
+                    // enabled by default
+                    repo.remove("enabled");
+                } else {
+                    repo["enabled"] = toml_edit::value(false);
+                }
+                true
+            })?;
+            config.enabled = enabled;
+            return Ok(config);
+        }
+        Self::rewrite_repo_file(&config.file, |line| {
+            let url = line.strip_prefix(DISABLED_PREFIX).unwrap_or(line).trim();
+            if url != config.url {
+                Some(line.to_string())
+            } else if enabled {
+                Some(url.to_string())
+            } else {
+                Some(format!("{} {}", DISABLED_PREFIX, url))
+            }
+        })?;
+        config.enabled = enabled;
+        Ok(config)
+    }
+
     fn extract_host(path: &str) -> Option<&str> {
         path.split("://")
             .nth(1)?
@@ -182,6 +660,11 @@ impl RepoManager {
                     pubpath: format!("{}/{}", url, PUB_TOML),
                     name: host.clone(),
                     pubkey: None,
+                    url: url.to_string(),
+                    mirrors: Vec::new(),
+                    priority: 0,
+                    pubkey_fingerprint: None,
+                    auth_error: None,
                 },
             )
             .is_none()
@@ -192,6 +675,69 @@ impl RepoManager {
         Ok(())
     }
 
+    /// Add a remote declared in a repository file. Credentials are read from install_path.
+    pub fn add_repo(
+        &mut self,
+        repo: &RepoFileEntry,
+        target: &str,
+        install_path: &Path,
+    ) -> Result<(), Error> {
+        let name = repo.remote_name()?;
+        let url = repo.url.trim_end_matches('/');
+        let mirrors: Vec<String> = repo
+            .mirrors
+            .iter()
+            .map(|m| m.trim_end_matches('/').to_string())
+            .collect();
+        let target = repo.target.as_deref().unwrap_or(target);
+        let pubkey = match &repo.pubkey {
+            Some(key) => Some(
+                hex::decode(key.trim())
+                    .ok()
+                    .and_then(|key| RepoPublicKey::try_from(key).ok())
+                    .ok_or_else(|| Error::RepoKeyInvalid(name.clone()))?,
+            ),
+            None => None,
+        };
+        // other remotes are still usable if credentials of this one can't be read
+        let mut auth_error = None;
+        if let Some(auth) = &repo.auth {
+            match auth.authorization(install_path) {
+                Ok(Some(value)) => {
+                    for prefix in std::iter::once(url).chain(mirrors.iter().map(String::as_str)) {
+                        self.download_backend
+                            .set_authorization(&format!("{prefix}/"), &value);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => auth_error = Some(err.to_string()),
+            }
+        }
+
+        if self
+            .remote_map
+            .insert(
+                name.clone(),
+                RemotePath {
+                    path: format!("{}/{}", url, target),
+                    pubpath: format!("{}/{}", url, PUB_TOML),
+                    name: name.clone(),
+                    pubkey,
+                    url: url.to_string(),
+                    mirrors,
+                    priority: repo.priority,
+                    pubkey_fingerprint: repo.pubkey_fingerprint.clone(),
+                    auth_error,
+                },
+            )
+            .is_none()
+        {
+            self.remotes.push(name);
+        };
+
+        Ok(())
+    }
+
     /// Add a local directory target. Specify a host as a unique identifier.
     pub fn add_local(
         &mut self,
@@ -222,6 +768,11 @@ impl RepoManager {
                     pubpath: "".into(),
                     name: host.into(),
                     pubkey: Some(pubkey.pkey),
+                    url: "".into(),
+                    mirrors: Vec::new(),
+                    priority: 0,
+                    pubkey_fingerprint: None,
+                    auth_error: None,
                 },
             )
             .is_none()
@@ -231,51 +782,182 @@ impl RepoManager {
         Ok(())
     }
 
-    /// Download a toml file. Wrapper to local_search() + download().
+    /// Download a toml file from the first source having it, or from the pinned one.
+    /// Wrapper to get_remote_toml().
     fn sync_toml(&self, package_name: &PackageName) -> Result<(String, RemoteName), Error> {
         let file = format!("{package_name}.toml");
-        if let Some((r, path)) = self.local_search(&file)? {
-            let toml = fs::read_to_string(path)?;
-            return Ok((toml, r));
+        let pinned = self.preferences.get_pin(package_name.as_str());
+        if let Some(pin) = pinned {
+            if !self.remote_map.contains_key(pin) {
+                return Err(Error::RepoNotConfigured(pin.clone()));
+            }
         }
-        let mut writer = DownloadBackendWriter::ToBuf(Vec::new());
-        match self.download(&file, None, &mut writer) {
-            Ok(r) => {
-                let text = writer.to_inner_buf();
-                let toml = String::from_utf8(text)
-                    .map_err(|_| Error::ContentIsNotValidUnicode(file.into()))?;
-                Ok((toml, r))
+        for remote in self.search_order() {
+            if pinned.is_some_and(|pin| pin != remote) {
+                continue;
+            }
+            if let Some(toml) = self.get_remote_toml(remote, &file)? {
+                return Ok((toml, remote.clone()));
+            }
+        }
+        Err(PackageError::PackageNotFound(package_name.to_owned()).into())
+    }
+
+    /// Names of every source in the order they are searched: higher priority first,
+    /// then locals before remotes in the order they are added.
+    pub fn search_order(&self) -> Vec<&RemoteName> {
+        let mut order: Vec<&RemoteName> = self.locals.iter().chain(self.remotes.iter()).collect();
+        // stable sort keeps the order of same priority
+        order.sort_by_key(|r| std::cmp::Reverse(self.get_priority(r)));
+        order
+    }
+
+    /// Priority of a remote from preferences, or from its repository file
+    pub fn get_priority(&self, remote: &str) -> i64 {
+        self.preferences.get_priority(remote).unwrap_or_else(|| {
+            self.remote_map
+                .get(remote)
+                .map_or(0, |remote| remote.priority)
+        })
+    }
+
+    // downloads a file of a remote, trying its mirrors when it fails
+    fn download_from(
+        &self,
+        remote: &RemotePath,
+        remote_path: &str,
+        len: Option<u64>,
+        dest: &mut DownloadBackendWriter,
+    ) -> Result<(), DownloadError> {
+        if let Some(err) = &remote.auth_error {
+            return Err(DownloadError::Other(format!(
+                "credentials of repository {:?} can't be read: {err}",
+                remote.name
+            )));
+        }
+        let mut result = Ok(());
+        for url in remote.with_mirrors(remote_path) {
+            dest.reset()?;
+            result = self
+                .download_backend
+                .download(&url, len, dest, self.callback.clone());
+            if result.is_ok() {
+                break;
             }
-            Err(Error::ValidRepoNotFound) => {
-                Err(PackageError::PackageNotFound(package_name.to_owned()).into())
+        }
+        result
+    }
+
+    /// Remote a package is pinned to, if any
+    pub fn get_pin(&self, package: &str) -> Option<&RemoteName> {
+        self.preferences.get_pin(package)
+    }
+
+    /// Read or download a toml file from one source. Returns none if it doesn't have the file.
+    pub fn get_remote_toml(&self, rname: &RemoteName, file: &str) -> Result<Option<String>, Error> {
+        let Some(remote) = self.remote_map.get(rname) else {
+            return Err(Error::RepoNotConfigured(rname.clone()));
+        };
+        if remote.path.is_empty() {
+            // installer repository
+            return Ok(None);
+        }
+
+        if remote.is_local() {
+            let path = Path::new(&remote.path).join(file);
+            if !path.is_file() {
+                return Ok(None);
             }
-            Err(e) => Err(e),
+            return Ok(Some(fs::read_to_string(path)?));
+        }
+
+        let remote_path = format!("{}/{}", remote.path, file);
+        let mut writer = DownloadBackendWriter::ToBuf(Vec::new());
+        match self.download_from(remote, &remote_path, None, &mut writer) {
+            Ok(_) => {}
+            #[cfg(feature = "library")]
+            Err(DownloadError::HttpStatus(_)) => return Ok(None),
+            Err(e) => return Err(Error::Download(e)),
         }
+        let toml = String::from_utf8(writer.to_inner_buf())
+            .map_err(|_| Error::ContentIsNotValidUnicode(file.into()))?;
+        Ok(Some(toml))
     }
 
-    /// Download a pkgar file to specified path. Wrapper to local_search() + download().
+    /// Download a pkgar file from a remote to specified path, or locate it in a local one.
     fn sync_pkgar(
         &self,
         package_name: &PackageName,
+        remote: &RemotePath,
         len_hint: u64,
         dst_path: PathBuf,
-    ) -> Result<(PathBuf, RemoteName), Error> {
+    ) -> Result<PathBuf, Error> {
         let file = format!("{package_name}.pkgar");
-        if let Some((r, path)) = self.local_search(&file)? {
-            return Ok((path, r));
+        let not_found = || PackageError::PackageNotFound(package_name.to_owned()).into();
+        if remote.path.is_empty() {
+            // installer repository
+            return Err(not_found());
         }
+        if remote.is_local() {
+            let path = Path::new(&remote.path).join(&file);
+            return if path.is_file() {
+                Ok(path)
+            } else {
+                Err(not_found())
+            };
+        }
+
+        let remote_path = format!("{}/{}", remote.path, file);
         let mut writer = DownloadBackendWriter::ToFile(File::create(&dst_path)?);
-        match self.download(&file, Some(len_hint), &mut writer) {
-            Ok(r) => Ok((dst_path, r)),
-            Err(Error::ValidRepoNotFound) => {
-                Err(PackageError::PackageNotFound(package_name.to_owned()).into())
-            }
-            Err(e) => Err(e),
+        match self.download_from(remote, &remote_path, Some(len_hint), &mut writer) {
+            Ok(_) => Ok(dst_path),
+            #[cfg(feature = "library")]
+            Err(DownloadError::HttpStatus(_)) => Err(not_found()),
+            Err(e) => Err(Error::Download(e)),
         }
     }
 
-    pub fn get_local_path(&self, remote: &RemoteName, file: &str, ext: &str) -> PathBuf {
-        self.download_path.join(format!("{}_{file}.{ext}", remote))
+    /// path of a downloaded pkgar by its blake3 hash, shared by every remote
+    pub fn get_cache_path(&self, blake3: &str) -> PathBuf {
+        self.download_path
+            .join("blake3")
+            .join(format!("{blake3}.pkgar"))
+    }
+
+    /// Remove downloaded files. If keep is specified, only removes archives which hash is not
+    /// listed, partial downloads and archives from older cache layout.
+    /// Archives of previous builds are removed too, so rolling back to them downloads them again.
+    /// Returns count and total size of removed files.
+    pub fn clean_cache(&self, keep: Option<&BTreeSet<String>>) -> Result<(usize, u64), Error> {
+        let mut removed = (0, 0);
+        let blake3_dir = self.download_path.join("blake3");
+        for dir in [&self.download_path, &blake3_dir] {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                let stale = match keep {
+                    None => true,
+                    Some(keep) if dir == &blake3_dir => file_name
+                        .strip_suffix(".pkgar")
+                        .is_none_or(|hash| !keep.contains(hash)),
+                    // public keys are kept
+                    Some(_) => file_name.ends_with(".pkgar") || file_name.ends_with(".part"),
+                };
+                if stale {
+                    removed.1 += entry.metadata()?.len();
+                    fs::remove_file(entry.path())?;
+                    removed.0 += 1;
+                }
+            }
+        }
+        Ok(removed)
     }
 
     /// Downloads all keys
@@ -284,35 +966,71 @@ impl RepoManager {
         if !download_dir.is_dir() {
             fs::create_dir_all(download_dir)?;
         }
-        for (_, remote) in self.remote_map.iter_mut() {
+        let mut pubkeys = Vec::new();
+        for remote in self.remote_map.values() {
             if remote.pubkey.is_some() {
                 continue;
             }
             // download key if not exists
-            if remote.pubkey.is_none() {
-                let local_keypath = download_dir.join(format!("pub_key_{}.toml", remote.name));
-                if !local_keypath.exists() {
-                    self.download_backend.download_to_file(
-                        &remote.pubpath,
-                        None,
-                        &local_keypath,
-                        self.callback.clone(),
-                    )?;
+            let local_keypath = download_dir.join(format!("pub_key_{}.toml", remote.name));
+            if !local_keypath.exists() {
+                // other processes may read the key while it is downloaded
+                let part_path =
+                    local_keypath.with_extension(format!("toml.{}.part", process::id()));
+                let mut writer = DownloadBackendWriter::ToFile(File::create(&part_path)?);
+                if let Err(e) = self.download_from(remote, &remote.pubpath, None, &mut writer) {
+                    let _ = fs::remove_file(&part_path);
+                    return Err(e.into());
                 }
-                let pubkey = RepoPublicKeyFile::open(local_keypath)?;
-                remote.pubkey = Some(pubkey.pkey);
+                fs::rename(&part_path, &local_keypath)?;
+            }
+            let pubkey = RepoPublicKeyFile::open(&local_keypath)?;
+            if let Some(fingerprint) = &remote.pubkey_fingerprint {
+                if let Err(err) = Self::check_fingerprint(&remote.name, &pubkey.pkey, fingerprint) {
+                    fs::remove_file(&local_keypath)?;
+                    return Err(err);
+                }
+            }
+            pubkeys.push((remote.name.clone(), pubkey.pkey));
+        }
+        for (name, pubkey) in pubkeys {
+            if let Some(remote) = self.remote_map.get_mut(&name) {
+                remote.pubkey = Some(pubkey);
             }
         }
 
         Ok(())
     }
 
+    /// check a public key of a remote against its configured fingerprint
+    #[cfg(feature = "library")]
+    pub(crate) fn check_fingerprint(
+        name: &str,
+        pubkey: &RepoPublicKey,
+        fingerprint: &str,
+    ) -> Result<(), Error> {
+        if blake3::hash(pubkey).to_hex().as_str() != fingerprint.trim().to_lowercase() {
+            return Err(Error::RepoKeyMismatch(name.into()));
+        }
+        Ok(())
+    }
+
+    // keys can't be checked without a hasher, they're never trusted
+    #[cfg(not(feature = "library"))]
+    pub(crate) fn check_fingerprint(
+        name: &str,
+        _pubkey: &RepoPublicKey,
+        _fingerprint: &str,
+    ) -> Result<(), Error> {
+        Err(Error::RepoKeyUnverifiable(name.into()))
+    }
+
     /// Download to dest and report which remotes it's downloaded from.
     pub fn download(
         &self,
         file: &str,
         len: Option<u64>,
-        mut dest: &mut DownloadBackendWriter,
+        dest: &mut DownloadBackendWriter,
     ) -> Result<RemoteName, Error> {
         if !self.download_path.exists() {
             fs::create_dir_all(self.download_path.clone())?;
@@ -328,10 +1046,7 @@ impl RepoManager {
             }
 
             let remote_path = format!("{}/{}", remote.path, file);
-            let res =
-                self.download_backend
-                    .download(&remote_path, len, &mut dest, self.callback.clone());
-            match res {
+            match self.download_from(remote, &remote_path, len, dest) {
                 Ok(_) => return Ok(rname.into()),
                 #[cfg(feature = "library")]
                 Err(DownloadError::HttpStatus(_)) => continue,
@@ -381,27 +1096,41 @@ impl RepoManager {
         Ok(None)
     }
 
-    /// Download a pkgar file to the download path. Wrapper to sync_pkgar().
+    /// Download a pkgar file from a remote to the download path, named by its blake3 hash if known.
+    /// Wrapper to sync_pkgar().
     pub fn get_package_pkgar(
         &self,
         package: &PackageName,
+        remote: &RemoteName,
+        blake3: &str,
         len_hint: u64,
     ) -> Result<(PathBuf, &RemotePath), Error> {
-        let local_path = self.get_local_path(&"".to_string(), package.as_str(), "pkgar");
-        let (local_path, remote) = self.sync_pkgar(&package, len_hint, local_path)?;
-        if let Some(r) = self.remote_map.get(&remote) {
-            if r.is_local() {
-                return Ok((local_path, r));
-            }
-            let new_local_path = self.get_local_path(&r.name, package.as_str(), "pkgar");
-            if new_local_path != local_path {
-                fs::rename(&local_path, &new_local_path)?;
-            }
-            Ok((new_local_path, r))
+        let Some(remote) = self.remote_map.get(remote) else {
+            return Err(Error::RepoNotConfigured(remote.clone()));
+        };
+        let cache_path = if blake3.is_empty() {
+            self.download_path.join(format!("{package}.pkgar"))
         } else {
-            // the pubkey cache is failing to download?
-            Err(Error::RepoCacheNotFound(package.clone()))
+            self.get_cache_path(blake3)
+        };
+        if let Some(parent) = cache_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // never leave an incomplete archive in the cache. Downloads only hold the shared lock,
+        // so each process writes its own part file and renames it atomically.
+        let part_path = cache_path.with_extension(format!("pkgar.{}.part", process::id()));
+        let local_path = match self.sync_pkgar(package, remote, len_hint, part_path.clone()) {
+            Ok(path) => path,
+            Err(err) => {
+                let _ = fs::remove_file(&part_path);
+                return Err(err);
+            }
+        };
+        if local_path != part_path {
+            return Ok((local_path, remote));
         }
+        fs::rename(&part_path, &cache_path)?;
+        Ok((cache_path, remote))
     }
 
     /// Fetch a toml file. Wrapper to sync_toml() with notifies fetch callback.
@@ -409,4 +1138,380 @@ impl RepoManager {
         self.callback.borrow_mut().fetch_package_name(&package);
         self.sync_toml(package)
     }
+
+    /// Fetch a toml file from one source. Wrapper to get_remote_toml() with notifies fetch callback.
+    pub fn get_package_toml_from(
+        &self,
+        package: &PackageName,
+        remote: &RemoteName,
+    ) -> Result<String, Error> {
+        self.callback.borrow_mut().fetch_package_name(package);
+        self.get_remote_toml(remote, &format!("{package}.toml"))?
+            .ok_or_else(|| PackageError::PackageNotFound(package.to_owned()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repo_preferences() {
+        let preferences: RepoPreferences = toml::from_str(
+            r#"
+            [priority]
+            "internal.example.com" = 10
+
+            [pin]
+            "mesa*" = "internal.example.com"
+            "mesa-demos" = "static.redox-os.org"
+            "lib?" = "other"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(preferences.get_priority("internal.example.com"), Some(10));
+        assert_eq!(preferences.get_priority("static.redox-os.org"), None);
+        let pin = |package| preferences.get_pin(package).map(String::as_str);
+        assert_eq!(pin("mesa"), Some("internal.example.com"));
+        assert_eq!(pin("mesa-glu"), Some("internal.example.com"));
+        assert_eq!(pin("mesa-demos"), Some("static.redox-os.org"));
+        assert_eq!(pin("libc"), Some("other"));
+        assert_eq!(pin("libcc"), None);
+        assert_eq!(pin("nano"), None);
+    }
+
+    #[test]
+    fn test_read_repo_file() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join("pkg_test_read_repo_file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        let legacy = dir.join("10_redox");
+        fs::write(
+            &legacy,
+            "# comment\nhttps://static.redox-os.org/pkg\n# disabled: https://other.org/pkg\n",
+        )?;
+        let repos = RepoManager::read_repo_file(&legacy)?;
+        assert_eq!(repos.len(), 2);
+        assert_eq!(repos[0].remote_name()?, "static.redox-os.org");
+        assert!(repos[0].enabled);
+        assert!(!repos[1].enabled);
+
+        let toml = dir.join("50_internal.toml");
+        fs::write(
+            &toml,
+            r#"
+            [[repo]]
+            name = "internal"
+            url = "https://pkg.example.com/redox"
+            mirrors = ["https://mirror.example.com/redox"]
+            priority = 10
+            target = "aarch64-unknown-redox"
+
+            [repo.auth]
+            token = "secret"
+
+            [[repo]]
+            url = "https://pkg.example.com/staging"
+            enabled = false
+            "#,
+        )?;
+        let repos = RepoManager::read_repo_file(&toml)?;
+        assert_eq!(repos.len(), 2);
+        assert_eq!(repos[0].remote_name()?, "internal");
+        assert_eq!(repos[0].priority, 10);
+        assert_eq!(repos[0].target.as_deref(), Some("aarch64-unknown-redox"));
+        let auth = repos[0].auth.as_ref().unwrap().authorization(&dir)?;
+        assert_eq!(auth.as_deref(), Some("Bearer secret"));
+        assert_eq!(repos[1].remote_name()?, "pkg.example.com");
+        assert!(!repos[1].enabled);
+
+        RepoManager::rewrite_toml_repo_file(&toml, "internal", |_| false)?;
+        let repos = RepoManager::read_repo_file(&toml)?;
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].url, "https://pkg.example.com/staging");
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_basic_auth() -> Result<(), Error> {
+        let auth = RepoAuth {
+            username: Some("Aladdin".into()),
+            password: Some("open sesame".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            auth.authorization(Path::new("/"))?.as_deref(),
+            Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")
+        );
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(RepoAuth::default().authorization(Path::new("/"))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_config() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_repo_config");
+        let _ = fs::remove_dir_all(&sysroot);
+        let repos_path = sysroot.join(PACKAGES_REMOTE_DIR);
+        let enabled = |name: &str| -> Result<Option<bool>, Error> {
+            Ok(RepoManager::read_repo_configs(&sysroot, None)?
+                .into_iter()
+                .find(|r| r.name == name)
+                .map(|r| r.enabled))
+        };
+
+        let path = RepoManager::add_repo_config(&sysroot, "https://static.redox-os.org/pkg/")?;
+        assert_eq!(path, repos_path.join("50_static.redox-os.org"));
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "https://static.redox-os.org/pkg\n"
+        );
+        assert!(matches!(
+            RepoManager::add_repo_config(&sysroot, "https://static.redox-os.org/other"),
+            Err(Error::RepoExists(_))
+        ));
+
+        let shared = repos_path.join("10_shared");
+        fs::write(
+            &shared,
+            "# mirrors\nhttps://a.example.com/pkg\nhttps://b.example.com/pkg\n",
+        )?;
+        assert_eq!(enabled("a.example.com")?, Some(true));
+
+        RepoManager::set_repo_enabled(&sysroot, "a.example.com", false)?;
+        assert_eq!(enabled("a.example.com")?, Some(false));
+        assert_eq!(enabled("b.example.com")?, Some(true));
+        assert_eq!(
+            fs::read_to_string(&shared)?,
+            "# mirrors\n# disabled: https://a.example.com/pkg\nhttps://b.example.com/pkg\n"
+        );
+        RepoManager::set_repo_enabled(&sysroot, "https://a.example.com/pkg", true)?;
+        assert_eq!(enabled("a.example.com")?, Some(true));
+
+        // by name and by URL, other repositories and comments are kept
+        RepoManager::remove_repo_config(&sysroot, "a.example.com")?;
+        RepoManager::remove_repo_config(&sysroot, "https://static.redox-os.org/pkg")?;
+        assert_eq!(enabled("a.example.com")?, None);
+        assert_eq!(enabled("static.redox-os.org")?, None);
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_to_string(&shared)?,
+            "# mirrors\nhttps://b.example.com/pkg\n"
+        );
+        assert!(matches!(
+            RepoManager::remove_repo_config(&sysroot, "a.example.com"),
+            Err(Error::RepoNotConfigured(_))
+        ));
+
+        // a file with nothing but comments left is removed
+        RepoManager::remove_repo_config(&sysroot, "b.example.com")?;
+        assert!(!shared.exists());
+        assert!(RepoManager::read_repo_configs(&sysroot, None)?.is_empty());
+
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml_repo_config() -> Result<(), Error> {
+        let sysroot = std::env::temp_dir().join("pkg_test_toml_repo_config");
+        let _ = fs::remove_dir_all(&sysroot);
+        let repos_path = sysroot.join(PACKAGES_REMOTE_DIR);
+        fs::create_dir_all(&repos_path)?;
+        let path = repos_path.join("50_internal.toml");
+        let original = r#"# internal repositories
+[[repo]]
+name = "internal" # production
+url = "https://pkg.example.com/redox"
+
+[repo.auth]
+username = "ci"
+password = "hunter2"
+
+# staging builds
+[[repo]]
+url = "https://staging.example.com/redox"
+"#;
+        fs::write(&path, original)?;
+
+        RepoManager::set_repo_enabled(&sysroot, "internal", false)?;
+        let data = fs::read_to_string(&path)?;
+        assert_eq!(
+            data,
+            original.replace(
+                "url = \"https://pkg.example.com/redox\"\n",
+                "url = \"https://pkg.example.com/redox\"\nenabled = false\n"
+            )
+        );
+        RepoManager::set_repo_enabled(&sysroot, "internal", true)?;
+        assert_eq!(fs::read_to_string(&path)?, original);
+
+        RepoManager::remove_repo_config(&sysroot, "staging.example.com")?;
+        let data = fs::read_to_string(&path)?;
+        assert_eq!(
+            data,
+            original[..original.find("\n\n# staging").unwrap() + 1]
+        );
+        RepoManager::remove_repo_config(&sysroot, "internal")?;
+        assert!(!path.exists());
+
+        let _ = fs::remove_dir_all(&sysroot);
+        Ok(())
+    }
+
+    #[test]
+    fn test_auth_error_per_remote() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join("pkg_test_auth_error_per_remote");
+        let _ = fs::remove_dir_all(&dir);
+        let mut manager = mock_repo_manager(&dir);
+        let mut broken = RepoFileEntry::from_url("https://broken.example.com/pkg", true);
+        broken.auth = Some(RepoAuth {
+            token_file: Some("/etc/pkg/missing.token".into()),
+            ..Default::default()
+        });
+        manager.add_repo(&broken, "x86_64-unknown-redox", &dir)?;
+        let local = dir.join("local");
+        fs::create_dir_all(local.join("x86_64-unknown-redox"))?;
+        fs::write(
+            local.join("x86_64-unknown-redox/nano.toml"),
+            "name = \"nano\"\n",
+        )?;
+        RepoPublicKeyFile::new([1; 32]).save(local.join(PUB_TOML))?;
+        manager.add_local(
+            "local",
+            &local.to_string_lossy(),
+            "x86_64-unknown-redox",
+            &local,
+        )?;
+
+        let remote = &manager.remote_map["broken.example.com"];
+        assert!(remote
+            .auth_error
+            .as_ref()
+            .unwrap()
+            .contains("missing.token"));
+        let err = manager
+            .get_remote_toml(&"broken.example.com".into(), "nano.toml")
+            .unwrap_err();
+        assert!(err.to_string().contains("broken.example.com"), "{err}");
+        let nano = manager.get_remote_toml(&"local".into(), "nano.toml")?;
+        assert_eq!(nano.as_deref(), Some("name = \"nano\"\n"));
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_package_pkgar_from_remote() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join("pkg_test_get_package_pkgar_from_remote");
+        let _ = fs::remove_dir_all(&dir);
+        let mut manager = mock_repo_manager(&dir.join("cache"));
+        for (remote, packages) in [("a", &["nano"][..]), ("b", &["nano", "vim"])] {
+            let path = dir.join(remote);
+            fs::create_dir_all(path.join("x86_64-unknown-redox"))?;
+            RepoPublicKeyFile::new([1; 32]).save(path.join(PUB_TOML))?;
+            for package in packages {
+                fs::write(
+                    path.join(format!("x86_64-unknown-redox/{package}.pkgar")),
+                    remote,
+                )?;
+            }
+            manager.add_local(
+                remote,
+                &path.to_string_lossy(),
+                "x86_64-unknown-redox",
+                &path,
+            )?;
+        }
+        let nano = PackageName::new("nano").unwrap();
+        let vim = PackageName::new("vim").unwrap();
+
+        let (path, remote) = manager.get_package_pkgar(&nano, &"b".into(), "", 0)?;
+        assert_eq!(remote.name, "b");
+        assert_eq!(fs::read_to_string(path)?, "b");
+        // never taken from another remote
+        assert!(matches!(
+            manager.get_package_pkgar(&vim, &"a".into(), "", 0),
+            Err(Error::Package(PackageError::PackageNotFound(_)))
+        ));
+        assert!(matches!(
+            manager.get_package_pkgar(&nano, &"c".into(), "", 0),
+            Err(Error::RepoNotConfigured(_))
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_fingerprint() {
+        let check = |fingerprint: &str| RepoManager::check_fingerprint("a", &[1; 32], fingerprint);
+        #[cfg(feature = "library")]
+        {
+            let fingerprint = blake3::hash(&[1; 32]).to_hex().as_str().to_uppercase();
+            assert!(check(&fingerprint).is_ok());
+            assert!(matches!(check("00"), Err(Error::RepoKeyMismatch(_))));
+        }
+        #[cfg(not(feature = "library"))]
+        assert!(matches!(check("00"), Err(Error::RepoKeyUnverifiable(_))));
+    }
+
+    fn mock_repo_manager(download_path: &Path) -> RepoManager {
+        let callback = Rc::new(RefCell::new(crate::callback::SilentCallback::new()));
+        let backend = crate::net_backend::CurlBackend::new().unwrap();
+        let mut manager = RepoManager::new(callback, Box::new(backend));
+        manager.set_download_path(download_path.to_path_buf());
+        manager
+    }
+
+    #[test]
+    fn test_clean_cache() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join("pkg_test_clean_cache");
+        let _ = fs::remove_dir_all(&dir);
+        let manager = mock_repo_manager(&dir);
+        let files = [
+            "blake3/used.pkgar",
+            "blake3/unused.pkgar",
+            "blake3/partial.pkgar.1234.part",
+            "static.redox-os.org_nano.pkgar",
+            "pub_key_static.redox-os.org.toml",
+        ];
+        let write_files = || -> Result<(), Error> {
+            fs::create_dir_all(dir.join("blake3"))?;
+            for file in files {
+                fs::write(dir.join(file), "data")?;
+            }
+            Ok(())
+        };
+        write_files()?;
+
+        let keep = BTreeSet::from(["used".to_string()]);
+        assert_eq!(manager.clean_cache(Some(&keep))?, (3, 12));
+        for file in files {
+            let kept = file == "blake3/used.pkgar" || file.starts_with("pub_key_");
+            assert_eq!(dir.join(file).exists(), kept, "{file}");
+        }
+
+        write_files()?;
+        assert_eq!(manager.clean_cache(None)?, (5, 20));
+        for file in files {
+            assert!(!dir.join(file).exists(), "{file}");
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("?a*", "ba"));
+        assert!(!glob_match("a*b", "ac"));
+        assert!(!glob_match("abc", "ab"));
+    }
 }
@@ -6,7 +6,10 @@ use pkg::callback::IndicatifCallback;
 #[cfg(not(feature = "indicatif"))]
 use pkg::callback::PlainCallback;
 
-use pkg::{Library, PackageName, PackageState};
+use pkg::{
+    backend::Error, Journal, JournalItem, Library, PackageAction, PackageFileEntry, PackageName,
+    PackageState,
+};
 
 #[test]
 fn test_pkg_install() -> Result<(), Box<dyn std::error::Error>> {
@@ -94,7 +97,8 @@ fn test_pkg_update() -> Result<(), Box<dyn std::error::Error>> {
     let mut p = PackageState::from_toml(&file)?;
     p.installed.get_mut("ncurses").unwrap().blake3 = "invalid".into();
     fs::write(&pkg_path, p.to_toml())?;
-    // reload metadata
+    // reload metadata, the lock is held until dropped
+    drop(library);
     library = Library::new_remote(
         &vec!["https://static.redox-os.org/pkg"],
         tmp_dir,
@@ -108,3 +112,142 @@ fn test_pkg_update() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// leaves the install of a package as if power was lost while its files were committed:
+// only some files are in place, and neither its head nor packages.toml is written
+fn interrupt_install(
+    tmp_dir: &std::path::Path,
+    installed: &PackageState,
+    name: &PackageName,
+    files: &[PackageFileEntry],
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::fs;
+
+    let state = &installed.installed[name];
+    let item = JournalItem {
+        name: name.clone(),
+        action: PackageAction::Install,
+        archive: Some(tmp_dir.join(format!("var/cache/pkg/blake3/{}.pkgar", state.blake3))),
+        pubkey: Some(installed.pubkeys[&state.remote].clone()),
+        old_blake3: String::new(),
+        new_blake3: state.blake3.clone(),
+    };
+    let mut old_state = installed.clone();
+    old_state.installed.remove(name);
+    Journal {
+        old_state: old_state.clone(),
+        new_state: installed.clone(),
+        items: vec![item],
+    }
+    .to_sysroot(tmp_dir)?;
+
+    for file in files.iter().step_by(2) {
+        fs::remove_file(tmp_dir.join(&file.path))?;
+    }
+    fs::remove_file(tmp_dir.join(format!("var/lib/packages/{name}.pkgar_head")))?;
+    old_state.to_sysroot(tmp_dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_pkg_resume() -> Result<(), Box<dyn std::error::Error>> {
+    use std::fs;
+
+    #[cfg(feature = "indicatif")]
+    let callback = Rc::new(RefCell::new(IndicatifCallback::new()));
+
+    #[cfg(not(feature = "indicatif"))]
+    let callback = Rc::new(RefCell::new(PlainCallback::new()));
+
+    let tmp_dir = std::env::current_dir()?.join("tests/staging_resume");
+
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+    }
+    let pkg_d_dir = tmp_dir.join("etc/pkg.d");
+    fs::create_dir_all(&pkg_d_dir)?;
+    fs::write(
+        pkg_d_dir.join("50_redox"),
+        "https://static.redox-os.org/pkg",
+    )?;
+
+    let target = "x86_64-unknown-redox";
+    let name = PackageName::new("ncurses")?;
+    let mut library = Library::new(&tmp_dir, target, callback.clone())?;
+    library.install(vec![name.clone()])?;
+    library.apply()?;
+    let installed = PackageState::from_sysroot(&tmp_dir)?;
+    let files = library.get_package_files(&name)?;
+    assert!(files.len() > 1);
+    drop(library);
+
+    // roll forward puts every file of the new build in place
+    interrupt_install(&tmp_dir, &installed, &name, &files)?;
+    let mut library = Library::new(&tmp_dir, target, callback.clone())?;
+    assert!(library.get_interrupted().is_some());
+    assert!(matches!(
+        library.apply(),
+        Err(Error::TransactionInterrupted)
+    ));
+    library.resume(false)?;
+    assert!(library.get_interrupted().is_none());
+    assert!(library.verify(vec![name.clone()])?.is_empty());
+    let state = PackageState::from_sysroot(&tmp_dir)?;
+    assert_eq!(
+        state.installed[&name].blake3,
+        installed.installed[&name].blake3
+    );
+    assert!(Journal::from_sysroot(&tmp_dir)?.is_none());
+    drop(library);
+
+    // rollback removes every file the new build could have written
+    interrupt_install(&tmp_dir, &installed, &name, &files)?;
+    let mut library = Library::new(&tmp_dir, target, callback.clone())?;
+    library.resume(true)?;
+    let state = PackageState::from_sysroot(&tmp_dir)?;
+    assert!(!state.installed.contains_key(&name));
+    for file in &files {
+        assert!(!tmp_dir.join(&file.path).exists());
+    }
+    assert!(!tmp_dir
+        .join(format!("var/lib/packages/{name}.pkgar_head"))
+        .exists());
+    assert!(Journal::from_sysroot(&tmp_dir)?.is_none());
+    drop(library);
+
+    // rolling back an interrupted repair keeps the installed build
+    let mut library = Library::new(&tmp_dir, target, callback.clone())?;
+    library.install(vec![name.clone()])?;
+    library.apply()?;
+    drop(library);
+    let blake3 = installed.installed[&name].blake3.clone();
+    Journal {
+        old_state: installed.clone(),
+        new_state: installed.clone(),
+        items: vec![JournalItem {
+            name: name.clone(),
+            action: PackageAction::Update,
+            archive: Some(tmp_dir.join(format!("var/cache/pkg/blake3/{blake3}.pkgar"))),
+            pubkey: Some(installed.pubkeys[&installed.installed[&name].remote].clone()),
+            old_blake3: blake3.clone(),
+            new_blake3: blake3,
+        }],
+    }
+    .to_sysroot(&tmp_dir)?;
+    for file in files.iter().step_by(2) {
+        fs::remove_file(tmp_dir.join(&file.path))?;
+    }
+    let mut library = Library::new(&tmp_dir, target, callback.clone())?;
+    assert!(matches!(
+        library.repair(vec![name.clone()]),
+        Err(Error::TransactionInterrupted)
+    ));
+    assert!(Journal::from_sysroot(&tmp_dir)?.is_some());
+    library.resume(true)?;
+    assert!(library.verify(vec![name.clone()])?.is_empty());
+    assert!(PackageState::from_sysroot(&tmp_dir)?
+        .installed
+        .contains_key(&name));
+
+    Ok(())
+}